@@ -0,0 +1,109 @@
+//! Accuracy evaluation of detectors on annotated datasets.
+
+use std::path::Path;
+
+use gstreamed_common::coco_eval::{CategoryMap, CocoDataset, CocoEvaluator, EvalReport};
+use gstreamed_common::detector::Detector;
+use gstreamed_common::labels::LabelMap;
+use gstreamed_common::mot::{self, MotRow};
+use gstreamed_common::mot_eval::{self, MotMetrics};
+
+/// Runs the `detector` over COCO dataset images in `images_dir`, described by `annotations` file,
+/// in batches of `batch_size` images, see [Detector::detect_batch],
+/// and evaluates detections against the annotations.
+///
+/// If `results` path is given, detections are also written into it in COCO results format.
+pub fn eval_coco(
+    images_dir: &Path,
+    annotations: &Path,
+    detector: &dyn Detector,
+    batch_size: usize,
+    results: Option<&Path>,
+) -> anyhow::Result<EvalReport> {
+    let dataset = CocoDataset::from_file(annotations)?;
+    log::info!(
+        "Loaded {} images with {} annotations from {annotations:?}",
+        dataset.images.len(),
+        dataset.annotations.len()
+    );
+    let categories = CategoryMap::new(&dataset.categories, detector.labels())?;
+    let mut evaluator = CocoEvaluator::new(&dataset, categories);
+
+    let mut processed = 0;
+    for chunk in dataset.images.chunks(batch_size) {
+        let og_images = chunk
+            .iter()
+            .map(|image| {
+                let path = images_dir.join(&image.file_name);
+                image::open(&path).map_err(|err| anyhow::anyhow!("Failed to open {path:?}: {err}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (batch, _) = detector.detect_batch(&og_images.iter().collect::<Vec<_>>())?;
+        for (image, detections) in chunk.iter().zip(batch) {
+            evaluator.add_detections(image.id, &detections);
+        }
+
+        // Log roughly every 100 images, whatever the batch size.
+        let previous = processed;
+        processed += chunk.len();
+        if processed / 100 > previous / 100 {
+            log::info!("Processed {processed}/{} images", dataset.images.len());
+        }
+    }
+
+    if let Some(results) = results {
+        evaluator.write_results(results)?;
+        log::info!("Wrote COCO results to {results:?}");
+    }
+
+    Ok(evaluator.evaluate())
+}
+
+/// Prints mAP and per-class AP of the `report`.
+pub fn print_coco_report(report: &EvalReport, labels: &LabelMap) {
+    println!("{:<20} {:>8} {:>12}", "class", "AP@0.5", "AP@0.5:0.95");
+    for class_ap in &report.per_class {
+        println!(
+            "{:<20} {:>8.4} {:>12.4}",
+            labels.name(class_ap.class),
+            class_ap.ap50,
+            class_ap.ap50_95
+        );
+    }
+    println!("mAP@0.5: {:.4}", report.map50);
+    println!("mAP@0.5:0.95: {:.4}", report.map50_95);
+}
+
+/// Evaluates `tracks` against MOTChallenge `ground_truth` file.
+pub fn eval_mot(ground_truth: &Path, tracks: &[MotRow]) -> anyhow::Result<MotMetrics> {
+    let ground_truth = mot::read_file(ground_truth)?;
+    log::info!(
+        "Evaluating {} tracked bboxes against {} ground truth bboxes",
+        tracks.len(),
+        ground_truth.len()
+    );
+    Ok(mot_eval::evaluate(&ground_truth, tracks))
+}
+
+/// Prints tracking `metrics`.
+pub fn print_mot_metrics(metrics: &MotMetrics) {
+    println!(
+        "HOTA: {:.4} (DetA: {:.4}, AssA: {:.4})",
+        metrics.hota, metrics.deta, metrics.assa
+    );
+    println!("MOTA: {:.4}", metrics.mota);
+    println!("MOTP: {:.4}", metrics.motp);
+    println!(
+        "IDF1: {:.4} (IDP: {:.4}, IDR: {:.4})",
+        metrics.idf1, metrics.idp, metrics.idr
+    );
+    println!("ID switches: {}", metrics.id_switches);
+    println!(
+        "TP: {}, FP: {}, FN: {}",
+        metrics.true_positives, metrics.false_positives, metrics.false_negatives
+    );
+    println!(
+        "GT: {} bboxes, {} ids; tracker: {} bboxes, {} ids",
+        metrics.num_gt_dets, metrics.num_gt_ids, metrics.num_tracker_dets, metrics.num_tracker_ids
+    );
+}
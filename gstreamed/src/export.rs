@@ -0,0 +1,75 @@
+//! Structured outputs of per-frame detections, next to the annotated image/video.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use gstreamed_common::bbox::Detections;
+use gstreamed_common::export::JsonLinesWriter;
+use gstreamed_common::labels::LabelMap;
+use gstreamed_common::mot::MotWriter;
+
+/// Path of the output of source `index`, out of `count` sources processed together, for the output `path` given.
+///
+/// With a single source, `path` is used as is, otherwise the source index is inserted before the extension,
+/// so `out.jsonl` becomes `out.0.jsonl`, `out.1.jsonl` and so on.
+pub fn source_path(path: &Path, index: usize, count: usize) -> PathBuf {
+    if count == 1 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}.{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{index}"),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Optional writers which processed frame detections are exported into.
+#[derive(Default)]
+pub struct Exports {
+    jsonl: Option<JsonLinesWriter<BufWriter<File>>>,
+    mot: Option<MotWriter<BufWriter<File>>>,
+}
+
+impl Exports {
+    /// Creates writers for the given output paths, if any.
+    pub fn create(jsonl: Option<&Path>, mot: Option<&Path>) -> anyhow::Result<Self> {
+        let mut exports = Self::default();
+        if let Some(path) = jsonl {
+            log::info!("Writing JSON Lines detections to {path:?}");
+            exports.jsonl = Some(JsonLinesWriter::create(path)?);
+        }
+        if let Some(path) = mot {
+            log::info!("Writing MOTChallenge rows to {path:?}");
+            exports.mot = Some(MotWriter::create(path)?);
+        }
+        Ok(exports)
+    }
+
+    /// Writes `detections` of the frame with 0-based index `frame` into all writers.
+    pub fn write_frame(
+        &mut self,
+        frame: u64,
+        detections: &Detections,
+        labels: &LabelMap,
+    ) -> anyhow::Result<()> {
+        if let Some(jsonl) = &mut self.jsonl {
+            jsonl.write_frame(frame, detections, labels)?;
+        }
+        if let Some(mot) = &mut self.mot {
+            mot.write_frame(frame, detections)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(jsonl) = &mut self.jsonl {
+            jsonl.flush()?;
+        }
+        if let Some(mot) = &mut self.mot {
+            mot.flush()?;
+        }
+        Ok(())
+    }
+}
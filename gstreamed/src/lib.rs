@@ -0,0 +1,8 @@
+//! Backend agnostic frame, video and image processing,
+//! shared between `gstreamed_ort` and `gstreamed_candle`.
+
+pub mod eval;
+pub mod export;
+pub mod process_image;
+pub mod process_mot;
+pub mod process_video;
@@ -0,0 +1,571 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use gstreamed::export::{source_path, Exports};
+use gstreamed::process_image::is_image;
+use gstreamed::process_video::VideoSource;
+use gstreamed::{eval, process_image, process_mot, process_video};
+use gstreamed_candle::inference::Which;
+use gstreamed_candle::CandleDetector;
+use gstreamed_common::detector::Detector;
+use gstreamed_common::discovery;
+use gstreamed_common::embedder::Embedder;
+use gstreamed_common::img_dimensions::ImgDimensions;
+use gstreamed_common::labels::LabelMap;
+use gstreamed_common::mot;
+use gstreamed_ort::output_layout::{ModelFamily, OutputLayout};
+use gstreamed_ort::{inference, OrtDetector, OrtEmbedder};
+use gstreamed_tracker::events::TrackRecordWriter;
+use gstreamed_tracker::{
+    ClassMode, LifecycleTracker, NoopTracker, TrackEvent, TrackEventKind, Tracker, TrackerConfig,
+    TrackerKind,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Object detection and tracking on images and videos, via gstreamer + ort or candle."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Detect objects on an image or videos, saving annotated output next to the input.
+    Detect(ProcessArgs),
+    /// Detect and track objects on videos, saving annotated output next to the input.
+    Track {
+        #[command(flatten)]
+        process: ProcessArgs,
+        #[command(flatten)]
+        track: TrackArgs,
+    },
+    /// Measure detector frame times on an image or videos.
+    Bench {
+        #[command(flatten)]
+        process: ProcessArgs,
+        /// How many times to run the detector, in case of an image or a directory of images.
+        #[arg(long, default_value = "100")]
+        runs: usize,
+    },
+    /// Track detections recorded in a MOTChallenge `det.txt` style file, without running a detector.
+    TrackMot {
+        /// Path to input MOTChallenge detections file.
+        input: PathBuf,
+        /// Path to output MOTChallenge tracks file.
+        #[arg(long, short)]
+        output: PathBuf,
+        /// Width of the frames the detections were made on.
+        #[arg(long)]
+        width: u32,
+        /// Height of the frames the detections were made on.
+        #[arg(long)]
+        height: u32,
+        #[command(flatten)]
+        tracker: TrackerArgs,
+    },
+    /// Evaluate detector or tracker accuracy on annotated data.
+    Eval {
+        #[command(subcommand)]
+        eval: EvalCommand,
+    },
+    /// Print information about the input media and the model.
+    Info {
+        /// Path to input image or video file.
+        input: Option<PathBuf>,
+        #[command(flatten)]
+        detector: DetectorArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum EvalCommand {
+    /// Run the detector over a directory of COCO dataset images,
+    /// reporting mAP@0.5, mAP@0.5:0.95 and per-class AP.
+    ///
+    /// For results comparable to published ones, use a low `--conf-threshold`, such as 0.001.
+    Coco {
+        /// Directory with the dataset images.
+        images: PathBuf,
+        /// COCO `instances_*.json` annotations file.
+        #[arg(long)]
+        annotations: PathBuf,
+        /// Write detections into this file in COCO results JSON format.
+        #[arg(long)]
+        results: Option<PathBuf>,
+        /// Images per forward pass.
+        #[arg(long, default_value = "8", value_parser = clap::value_parser!(u64).range(1..))]
+        batch_size: u64,
+        #[command(flatten)]
+        detector: DetectorArgs,
+    },
+    /// Compare tracks with MOTChallenge ground truth,
+    /// reporting HOTA, MOTA, MOTP, IDF1 and ID switches.
+    Mot {
+        /// MOTChallenge ground truth file (`gt.txt`).
+        #[arg(long)]
+        gt: PathBuf,
+        /// Tracker output to evaluate, as MOTChallenge rows.
+        #[arg(
+            long,
+            required_unless_present = "detections",
+            conflicts_with = "detections"
+        )]
+        tracks: Option<PathBuf>,
+        /// Instead of `--tracks`, track these MOTChallenge detections (`det.txt`) and evaluate them.
+        #[arg(long, requires_all = ["width", "height"])]
+        detections: Option<PathBuf>,
+        /// Width of the frames, required with `--detections`.
+        #[arg(long)]
+        width: Option<u32>,
+        /// Height of the frames, required with `--detections`.
+        #[arg(long)]
+        height: Option<u32>,
+        #[command(flatten)]
+        tracker: TrackerArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+struct ProcessArgs {
+    /// Path to input image (.jpeg/.png) or video file (.mp4/.mkv), or video stream URI (rtsp://...).
+    /// Multiple videos are processed together, with frames of all of them detected in a single batch.
+    /// A directory of images is processed in batches of `--batch-size` images.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Images per forward pass, for a directory of images.
+    #[arg(long, default_value = "8", value_parser = clap::value_parser!(u64).range(1..))]
+    batch_size: u64,
+    /// Whether to live playback the inference results.
+    #[arg(long, action, default_value = "false")]
+    live: bool,
+    /// Write detections of each frame into this file as JSON Lines.
+    /// With multiple inputs, the input index is added before the extension, e.g. `out.0.jsonl`.
+    #[arg(long)]
+    jsonl: Option<PathBuf>,
+    /// Write detections (or tracks) into this file as MOTChallenge rows.
+    /// With multiple inputs, the input index is added before the extension, e.g. `out.0.txt`.
+    #[arg(long)]
+    mot: Option<PathBuf>,
+    #[command(flatten)]
+    detector: DetectorArgs,
+}
+
+impl ProcessArgs {
+    /// Creates exports of the input with the given `index`, see [source_path].
+    fn exports(&self, index: usize) -> anyhow::Result<Exports> {
+        let path = |path: &Path| source_path(path, index, self.inputs.len());
+        Exports::create(
+            self.jsonl.as_deref().map(path).as_deref(),
+            self.mot.as_deref().map(path).as_deref(),
+        )
+    }
+
+    /// The single input, if any of the inputs `is_kind`, as only videos can be combined.
+    /// Inputs which are neither images, nor directories, are assumed to be videos,
+    /// which gstreamer will attempt to decode.
+    fn single(&self, is_kind: impl Fn(&Path) -> bool) -> anyhow::Result<Option<&Path>> {
+        if !self.inputs.iter().any(|input| is_kind(input)) {
+            return Ok(None);
+        }
+        let [input] = self.inputs.as_slice() else {
+            anyhow::bail!("Multiple inputs are only supported for videos");
+        };
+        Ok(Some(input))
+    }
+
+    /// The single image input, if the input is an image.
+    fn image(&self) -> anyhow::Result<Option<&Path>> {
+        self.single(is_image)
+    }
+
+    /// The single image directory input, if the input is a directory.
+    fn image_dir(&self) -> anyhow::Result<Option<&Path>> {
+        self.single(Path::is_dir)
+    }
+}
+
+/// Options of the `track` command, on top of [TrackerArgs].
+#[derive(Debug, Args)]
+struct TrackArgs {
+    #[command(flatten)]
+    tracker: TrackerArgs,
+    /// ReID onnx model (e.g. OSNet), whose appearance embeddings of detections are used
+    /// for re-identification, requires `--tracker visual-sort`.
+    #[arg(long)]
+    reid_model: Option<PathBuf>,
+    /// Write a JSON Lines record per track into this file, once the track terminates,
+    /// with its class, first/last frame and timestamp and the whole trajectory.
+    /// With multiple inputs, the input index is added before the extension, e.g. `tracks.0.jsonl`.
+    #[arg(long)]
+    track_events: Option<PathBuf>,
+    /// Run the detector only on every n-th frame, tracks are predicted on the frames in between.
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    detect_every: u64,
+}
+
+impl TrackArgs {
+    /// Creates the tracker configured by `config`, writing track records with `labels`, if requested,
+    /// for the input with `index` out of `count` inputs, see [source_path].
+    fn tracker(
+        &self,
+        config: &TrackerConfig,
+        labels: &LabelMap,
+        index: usize,
+        count: usize,
+    ) -> anyhow::Result<Box<dyn Tracker>> {
+        let tracker = gstreamed_tracker::tracker(config);
+        let Some(path) = &self.track_events else {
+            return Ok(tracker);
+        };
+        let path = source_path(path, index, count);
+        log::info!("Writing track records to {path:?}");
+        let mut writer = TrackRecordWriter::create(&path)?;
+        let labels = labels.clone();
+        let on_event = Box::new(move |event: TrackEvent| {
+            if event.kind != TrackEventKind::Terminated {
+                return;
+            }
+            if let Err(err) = writer
+                .write_track(&event, &labels)
+                .and_then(|_| writer.flush())
+            {
+                log::error!("Failed to write track {}: {err}", event.track_id);
+            }
+        });
+        Ok(Box::new(LifecycleTracker::new(tracker, config, on_event)))
+    }
+
+    /// Loads the ReID model as an [Embedder], if given.
+    fn embedder(
+        &self,
+        config: &TrackerConfig,
+        cuda: bool,
+    ) -> anyhow::Result<Option<Box<dyn Embedder>>> {
+        let Some(model) = &self.reid_model else {
+            return Ok(None);
+        };
+        anyhow::ensure!(
+            config.kind == TrackerKind::VisualSort,
+            "ReID model is only used by visual-sort tracker, got {:?}",
+            config.kind
+        );
+        let session = inference::load_session(model, cuda)?;
+        Ok(Some(Box::new(OrtEmbedder::new(session)?)))
+    }
+}
+
+/// Tracker parameters, each overriding the one from `--tracker-config`, if given.
+#[derive(Debug, Args)]
+struct TrackerArgs {
+    /// Tracker config file (.yaml or .json), with any of the fields below in snake_case.
+    #[arg(long)]
+    tracker_config: Option<PathBuf>,
+    /// Tracking algorithm, ByteTrack thresholds are set in the `byte_track` section of the config
+    /// [default: sort].
+    #[arg(long, value_enum)]
+    tracker: Option<TrackerKind>,
+    /// How classes are handled: `agnostic` matches by position only, `strict` never matches
+    /// a track with a detection of another class, `vote` sets track class by majority vote
+    /// over its history [default: agnostic].
+    #[arg(long, value_enum)]
+    class_mode: Option<ClassMode>,
+    /// How many frames a track is kept without observations before it is removed [default: 10].
+    #[arg(long)]
+    max_idle_epochs: Option<usize>,
+    /// Nominal frame rate, tracker epochs are its frame intervals, so dropped frames count as well
+    /// [default: the shortest interval between frame timestamps].
+    #[arg(long)]
+    frame_rate: Option<f32>,
+    /// Minimal IoU between a track prediction and a detection for them to be matched [default: 0.3].
+    #[arg(long)]
+    iou_threshold: Option<f32>,
+    /// SORT weights IoU by detection confidence, at least this much [default: 0.05].
+    #[arg(long)]
+    min_confidence: Option<f32>,
+    /// Kalman filter position weight [default: 0.05].
+    #[arg(long)]
+    position_weight: Option<f32>,
+    /// Kalman filter velocity weight [default: 0.00625].
+    #[arg(long)]
+    velocity_weight: Option<f32>,
+}
+
+impl TrackerArgs {
+    fn config(&self) -> anyhow::Result<TrackerConfig> {
+        let mut config = match &self.tracker_config {
+            Some(path) => TrackerConfig::from_file(path)?,
+            None => TrackerConfig::default(),
+        };
+        fn set<T: Copy>(field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        set(&mut config.kind, self.tracker);
+        set(&mut config.class_mode, self.class_mode);
+        set(&mut config.max_idle_epochs, self.max_idle_epochs);
+        set(&mut config.frame_rate, self.frame_rate.map(Some));
+        set(&mut config.iou_threshold, self.iou_threshold);
+        set(&mut config.min_confidence, self.min_confidence);
+        set(&mut config.position_weight, self.position_weight);
+        set(&mut config.velocity_weight, self.velocity_weight);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Inference library used to run the detector.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Backend {
+    /// onnxruntime via `ort`.
+    Ort,
+    /// `candle`.
+    Candle,
+}
+
+#[derive(Debug, Args)]
+struct DetectorArgs {
+    /// Inference backend to use.
+    #[arg(long, value_enum, default_value = "ort")]
+    backend: Backend,
+    /// Model file to use.
+    /// Yolov8 .onnx file for `ort` (defaults to `_models/yolov8s.onnx`),
+    /// yolov8 .safetensors file for `candle` (defaults to hf hub download).
+    #[arg(long, short)]
+    model: Option<PathBuf>,
+    /// Class names file (.txt with one name per line, .yaml or .json).
+    /// By default, names embedded in the onnx model metadata are used with `ort`,
+    /// falling back to COCO classes.
+    #[arg(long)]
+    labels: Option<PathBuf>,
+    /// Family of the `ort` model, `yolo`, `rt-detr` or `rt-detr-logits` for RT-DETR exports with raw class logits.
+    /// By default, it's read from model metadata, falling back to `yolo`.
+    #[arg(long, value_enum)]
+    model_family: Option<ModelFamily>,
+    /// Layout of the `ort` yolo model output.
+    /// By default, it's detected from the output shape and model metadata.
+    #[arg(long, value_enum)]
+    output_layout: Option<OutputLayout>,
+    /// Yolov8 model size, used by `candle` backend.
+    #[arg(long, value_enum, default_value = "s")]
+    which: Which,
+    /// Whether to attempt to use `cuda` hw acceleration.
+    /// With `ort`, this may silently fail and fallback to cpu acceleration presently.
+    #[arg(long, action, default_value = "false")]
+    cuda: bool,
+    /// Minimal detector confidence for a bbox to be kept.
+    #[arg(long, default_value = "0.25")]
+    conf_threshold: f32,
+    /// IoU threshold for non-maximum suppression.
+    #[arg(long, default_value = "0.45")]
+    nms_threshold: f32,
+}
+
+const DEFAULT_ORT_MODEL: &str = "_models/yolov8s.onnx";
+
+impl DetectorArgs {
+    fn ort_model(&self) -> &Path {
+        self.model
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_ORT_MODEL))
+    }
+
+    fn labels(&self) -> anyhow::Result<Option<LabelMap>> {
+        self.labels.as_deref().map(LabelMap::from_file).transpose()
+    }
+
+    fn load(&self) -> anyhow::Result<Box<dyn Detector>> {
+        let labels = self.labels()?;
+        Ok(match self.backend {
+            Backend::Ort => {
+                let session = inference::load_session(self.ort_model(), self.cuda)?;
+                Box::new(OrtDetector::new(
+                    session,
+                    labels,
+                    self.model_family,
+                    self.output_layout,
+                    self.conf_threshold,
+                    self.nms_threshold,
+                )?)
+            }
+            Backend::Candle => Box::new(CandleDetector::load(
+                self.which,
+                self.model.as_deref(),
+                labels.unwrap_or_default(),
+                self.cuda,
+                self.conf_threshold,
+                self.nms_threshold,
+            )?),
+        })
+    }
+}
+
+fn detect(args: ProcessArgs, track: Option<TrackArgs>) -> anyhow::Result<()> {
+    let detector = args.detector.load()?;
+    if let Some(input) = args.image()? {
+        anyhow::ensure!(track.is_none(), "Tracking requires a video input");
+        process_image::process_image(input, detector.as_ref(), args.exports(0)?)
+    } else if let Some(dir) = args.image_dir()? {
+        anyhow::ensure!(track.is_none(), "Tracking requires a video input");
+        process_image::process_image_dir(
+            dir,
+            detector.as_ref(),
+            args.batch_size as usize,
+            args.exports(0)?,
+        )
+    } else {
+        let config = track
+            .as_ref()
+            .map(|track| track.tracker.config())
+            .transpose()?;
+        let mut sources = Vec::with_capacity(args.inputs.len());
+        for (index, input) in args.inputs.iter().enumerate() {
+            let tracker = match (&track, &config) {
+                (Some(track), Some(config)) => {
+                    track.tracker(config, detector.labels(), index, args.inputs.len())?
+                }
+                _ => Box::new(NoopTracker),
+            };
+            sources.push(VideoSource {
+                input: input.clone(),
+                tracker,
+                exports: args.exports(index)?,
+            });
+        }
+        let (embedder, detect_every) = match (&track, &config) {
+            (Some(track), Some(config)) => (
+                track.embedder(config, args.detector.cuda)?,
+                track.detect_every,
+            ),
+            _ => (None, 1),
+        };
+        process_video::process_videos(sources, args.live, detect_every, detector, embedder)
+    }
+}
+
+fn bench(args: ProcessArgs, runs: usize) -> anyhow::Result<()> {
+    let detector = args.detector.load()?;
+    if let Some(input) = args.image()? {
+        process_image::bench_image(input, detector.as_ref(), runs)
+    } else if let Some(dir) = args.image_dir()? {
+        process_image::bench_image_dir(dir, detector.as_ref(), args.batch_size as usize, runs)
+    } else {
+        let mut sources = Vec::with_capacity(args.inputs.len());
+        for (index, input) in args.inputs.iter().enumerate() {
+            sources.push(VideoSource {
+                input: input.clone(),
+                tracker: Box::new(NoopTracker),
+                exports: args.exports(index)?,
+            });
+        }
+        process_video::process_videos(sources, args.live, 1, detector, None)
+    }
+}
+
+fn eval(command: EvalCommand) -> anyhow::Result<()> {
+    match command {
+        EvalCommand::Coco {
+            images,
+            annotations,
+            results,
+            batch_size,
+            detector,
+        } => {
+            let detector = detector.load()?;
+            let report = eval::eval_coco(
+                &images,
+                &annotations,
+                detector.as_ref(),
+                batch_size as usize,
+                results.as_deref(),
+            )?;
+            eval::print_coco_report(&report, detector.labels());
+        }
+        EvalCommand::Mot {
+            gt,
+            tracks,
+            detections,
+            width,
+            height,
+            tracker,
+        } => {
+            let tracks = match (tracks, detections, width, height) {
+                (Some(tracks), ..) => mot::read_file(&tracks)?,
+                (None, Some(detections), Some(width), Some(height)) => {
+                    let frame_dims = ImgDimensions::new(width as f32, height as f32);
+                    let frames = mot::read_detections(&detections, frame_dims)?;
+                    mot::detections_to_rows(&process_mot::track_frames(&frames, &tracker.config()?))
+                }
+                _ => anyhow::bail!("Either --tracks or --detections with frame size is required"),
+            };
+            let metrics = eval::eval_mot(&gt, &tracks)?;
+            eval::print_mot_metrics(&metrics);
+        }
+    }
+    Ok(())
+}
+
+fn info(input: Option<PathBuf>, args: DetectorArgs) -> anyhow::Result<()> {
+    if let Some(input) = input {
+        if is_image(&input) {
+            let image = image::open(&input)?;
+            println!("Image: {input:?}, {}x{}", image.width(), image.height());
+        } else {
+            gstreamer::init()?;
+            let file_info = discovery::discover(&input)?;
+            println!("{file_info:?}");
+        }
+    }
+
+    match args.backend {
+        Backend::Ort => {
+            let session = inference::load_session(args.ort_model(), args.cuda)?;
+            inference::print_session_info(&session)?;
+        }
+        Backend::Candle => {
+            println!("Candle yolov8 model: {:?}", args.which);
+            match &args.model {
+                Some(weights) => println!("Weights: {weights:?}"),
+                None => println!("Weights: hf hub download"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    // Initialize logging.
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                "warn,gstreamed=info,gstreamed_ort=info,gstreamed_candle=info".into()
+            }),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Detect(args) => detect(args, None),
+        Command::Track { process, track } => detect(process, Some(track)),
+        Command::Bench { process, runs } => bench(process, runs),
+        Command::TrackMot {
+            input,
+            output,
+            width,
+            height,
+            tracker,
+        } => process_mot::track_mot_detections(
+            &input,
+            &output,
+            ImgDimensions::new(width as f32, height as f32),
+            &tracker.config()?,
+        ),
+        Command::Eval { eval: command } => eval(command),
+        Command::Info { input, detector } => info(input, detector),
+    }
+}
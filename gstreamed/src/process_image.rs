@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use gstreamed_common::detector::Detector;
+use gstreamed_common::frame_times::{AggregatedTimes, FrameTimes};
+use gstreamed_tracker::NoopTracker;
+
+use crate::export::Exports;
+use crate::process_video::{add_detector_times, log_frame_time_stats, process_frame, track_frame};
+
+/// Whether the file at `path` is an image, judging by its file extension.
+pub fn is_image(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|os_str| os_str.to_str()),
+        Some("jpeg" | "jpg" | "png")
+    )
+}
+
+/// Paths of images in the `dir`, sorted by file name, skipping annotated outputs (`*.out.jpg`).
+fn image_paths(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_output = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| stem.ends_with(".out"));
+        if path.is_file() && is_image(&path) && !is_output {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Performs inference on a single image file.
+///
+/// Detections are also written into `exports`, as the only frame.
+pub fn process_image(
+    path: &Path,
+    detector: &dyn Detector,
+    mut exports: Exports,
+) -> anyhow::Result<()> {
+    let mut frame_times = FrameTimes::default();
+
+    // Read image.
+    let og_image = image::open(path)?;
+
+    // Process image.
+    let (img, detections) = process_frame(
+        detector,
+        &mut NoopTracker,
+        None,
+        og_image,
+        None,
+        &mut frame_times,
+    )?;
+    // NB! For a single image, ort times will be misleading,
+    // as the first time it's used, it does all kinds of lazy init.
+    log::debug!("{frame_times:?}");
+    // Save output.
+    let output_path = path.with_extension("out.jpg");
+    img.save(output_path)?;
+
+    exports.write_frame(0, &detections, detector.labels())?;
+    exports.flush()?;
+
+    Ok(())
+}
+
+/// Performs inference on all images in the `dir`, in batches of `batch_size` images, see [Detector::detect_batch].
+///
+/// Annotated images are saved next to the originals, like with [process_image].
+/// Detections are also written into `exports`, with images as frames, in the order of their file names.
+pub fn process_image_dir(
+    dir: &Path,
+    detector: &dyn Detector,
+    batch_size: usize,
+    mut exports: Exports,
+) -> anyhow::Result<()> {
+    let paths = image_paths(dir)?;
+    anyhow::ensure!(!paths.is_empty(), "No images found in {dir:?}");
+    log::info!(
+        "Processing {} images from {dir:?} in batches of {batch_size}",
+        paths.len()
+    );
+
+    let mut agg_times = AggregatedTimes::default();
+    let mut frame = 0;
+    for chunk in paths.chunks(batch_size) {
+        let mut frame_times = FrameTimes::default();
+
+        // Read images.
+        let start = Instant::now();
+        let images = chunk
+            .iter()
+            .map(|path| {
+                image::open(path).map_err(|err| anyhow::anyhow!("Failed to open {path:?}: {err}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        frame_times.frame_to_buffer = start.elapsed();
+
+        // Detect the whole batch at once.
+        let (batch, detector_times) = detector.detect_batch(&images.iter().collect::<Vec<_>>())?;
+        add_detector_times(&mut frame_times, &detector_times);
+
+        for ((path, image), detections) in chunk.iter().zip(images).zip(batch) {
+            let mut image_times = FrameTimes::default();
+            let (img, detections) = track_frame(
+                &mut NoopTracker,
+                None,
+                detector.labels(),
+                image,
+                detections,
+                None,
+                &mut image_times,
+            )?;
+            frame_times.annotation += image_times.annotation;
+
+            // Save output.
+            img.save(path.with_extension("out.jpg"))?;
+            exports.write_frame(frame, &detections, detector.labels())?;
+            frame += 1;
+        }
+
+        log::debug!("{frame_times:?}");
+        agg_times.push(frame_times);
+        log::info!("Processed {frame}/{} images", paths.len());
+    }
+    exports.flush()?;
+
+    // Print perf stats, ignoring first (outlier) batch.
+    log_frame_time_stats(&agg_times);
+
+    Ok(())
+}
+
+/// Runs the `detector` on a single image file `runs` times, and logs the frame time stats.
+pub fn bench_image(path: &Path, detector: &dyn Detector, runs: usize) -> anyhow::Result<()> {
+    let image = image::open(path)?;
+
+    let mut agg_times = AggregatedTimes::default();
+    for _ in 0..runs {
+        let (detections, frame_times) = detector.detect(&image)?;
+        log::debug!("{detections:?}");
+        agg_times.push(frame_times);
+    }
+    log_frame_time_stats(&agg_times);
+
+    Ok(())
+}
+
+/// Runs the `detector` on all images in the `dir`, in batches of `batch_size` images, `runs` times,
+/// and logs the frame time stats of the batches, without saving any outputs, see [process_image_dir].
+pub fn bench_image_dir(
+    dir: &Path,
+    detector: &dyn Detector,
+    batch_size: usize,
+    runs: usize,
+) -> anyhow::Result<()> {
+    let paths = image_paths(dir)?;
+    anyhow::ensure!(!paths.is_empty(), "No images found in {dir:?}");
+    // Images are read once, so only the detector is measured.
+    let images = paths
+        .iter()
+        .map(|path| {
+            image::open(path).map_err(|err| anyhow::anyhow!("Failed to open {path:?}: {err}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    log::info!(
+        "Benchmarking {} images from {dir:?} in batches of {batch_size}, {runs} times",
+        images.len()
+    );
+
+    let mut agg_times = AggregatedTimes::default();
+    for _ in 0..runs {
+        for chunk in images.chunks(batch_size) {
+            let (batch, detector_times) =
+                detector.detect_batch(&chunk.iter().collect::<Vec<_>>())?;
+            log::debug!("{batch:?}");
+            let mut frame_times = FrameTimes::default();
+            add_detector_times(&mut frame_times, &detector_times);
+            agg_times.push(frame_times);
+        }
+    }
+    log_frame_time_stats(&agg_times);
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use gstreamed_common::bbox::Detections;
+use gstreamed_common::img_dimensions::ImgDimensions;
+use gstreamed_common::mot::{self, MotWriter};
+use gstreamed_tracker::TrackerConfig;
+
+/// Frame rate MOT frames are timed at, unless [TrackerConfig::frame_rate] is given.
+/// Only sets the unit of time, as frames are timed by their frame numbers.
+const MOT_FRAME_RATE: f32 = 30.0;
+
+/// Tracks per frame `frames` of detections, keyed by frame index, with a new tracker configured by `config`,
+/// returning tracked bboxes of each frame.
+///
+/// MOT rows have no timestamps, so frames are timed by their frame numbers at the nominal frame rate,
+/// and frames without any rows count as dropped ones, see [gstreamed_tracker::clock].
+pub fn track_frames(
+    frames: &BTreeMap<u64, Detections>,
+    config: &TrackerConfig,
+) -> BTreeMap<u64, Detections> {
+    let frame_rate = config.frame_rate.unwrap_or(MOT_FRAME_RATE);
+    let config = TrackerConfig {
+        frame_rate: Some(frame_rate),
+        ..config.clone()
+    };
+    let mut tracker = gstreamed_tracker::tracker(&config);
+    let tracked = frames
+        .iter()
+        .map(|(&frame, detections)| {
+            let timestamp = Duration::from_secs_f64(frame as f64 / frame_rate as f64);
+            (frame, tracker.update(detections, Some(timestamp)))
+        })
+        .collect();
+    tracker.finish();
+    tracked
+}
+
+/// Tracks detections recorded in a MOTChallenge `det.txt` style file at `input`,
+/// without running a detector, and writes resulting tracks as MOTChallenge rows into `output`.
+///
+/// `frame_dims` are the dimensions of the frames the detections were made on.
+pub fn track_mot_detections(
+    input: &Path,
+    output: &Path,
+    frame_dims: ImgDimensions,
+    config: &TrackerConfig,
+) -> anyhow::Result<()> {
+    let frames = mot::read_detections(input, frame_dims)?;
+    log::info!("Read detections of {} frames from {input:?}", frames.len());
+
+    let mut writer = MotWriter::create(output)?;
+    for (frame, tracked) in track_frames(&frames, config) {
+        writer.write_frame(frame, &tracked)?;
+    }
+    writer.flush()?;
+    log::info!("Wrote tracks to {output:?}");
+
+    Ok(())
+}
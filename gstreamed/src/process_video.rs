@@ -0,0 +1,402 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gstreamed_common::annotate::annotate_image_with_bboxes;
+use gstreamed_common::bbox::Detections;
+use gstreamed_common::detector::Detector;
+use gstreamed_common::embedder::Embedder;
+use gstreamed_common::frame_times::{AggregatedTimes, FrameTimes};
+use gstreamed_common::labels::LabelMap;
+use gstreamed_common::pipeline::{build_pipeline, InferencePipeline};
+use gstreamed_common::{discovery, img_dimensions::ImgDimensions};
+use gstreamed_tracker::Tracker;
+use gstreamer as gst;
+use image::{DynamicImage, RgbImage};
+
+use crate::export::Exports;
+
+/// Legend size used when annotating frames.
+const LEGEND_SIZE: u32 = 14;
+
+/// Runs detection, tracking and annotation on the given `image`, see [track_frame].
+///
+/// Returns the annotated image, along with the (tracked) detections drawn on it.
+pub fn process_frame(
+    detector: &dyn Detector,
+    tracker: &mut dyn Tracker,
+    embedder: Option<&dyn Embedder>,
+    image: DynamicImage,
+    timestamp: Option<Duration>,
+    frame_times: &mut FrameTimes,
+) -> anyhow::Result<(DynamicImage, Detections)> {
+    let (detections, detector_times) = detector.detect(&image)?;
+    add_detector_times(frame_times, &detector_times);
+    track_frame(
+        tracker,
+        embedder,
+        detector.labels(),
+        image,
+        detections,
+        timestamp,
+        frame_times,
+    )
+}
+
+/// Adds times of the detector stages in `detector_times` to `frame_times`.
+pub(crate) fn add_detector_times(frame_times: &mut FrameTimes, detector_times: &FrameTimes) {
+    frame_times.buffer_resize += detector_times.buffer_resize;
+    frame_times.buffer_to_tensor += detector_times.buffer_to_tensor;
+    frame_times.forward_pass += detector_times.forward_pass;
+    frame_times.bbox_extraction += detector_times.bbox_extraction;
+    frame_times.nms += detector_times.nms;
+}
+
+/// Runs tracking and annotation on the given `image`, with `detections` of the detector.
+///
+/// If `embedder` is given, appearance features of detections are passed to the tracker too.
+/// `timestamp` is the presentation timestamp of the frame, if known.
+///
+/// Returns the annotated image, along with the (tracked) detections drawn on it.
+pub fn track_frame(
+    tracker: &mut dyn Tracker,
+    embedder: Option<&dyn Embedder>,
+    labels: &LabelMap,
+    image: DynamicImage,
+    mut detections: Detections,
+    timestamp: Option<Duration>,
+    frame_times: &mut FrameTimes,
+) -> anyhow::Result<(DynamicImage, Detections)> {
+    detections.timestamp = timestamp;
+
+    // Perform tracking, appearance features are computed as a part of it.
+    let start = Instant::now();
+    let detections = match embedder {
+        Some(embedder) => {
+            let features = embedder.embed(&image, &detections)?;
+            tracker.update_with_features(&detections, &features, timestamp)
+        }
+        None => tracker.update(&detections, timestamp),
+    };
+    frame_times.tracking = start.elapsed();
+    log::debug!("{detections:?}");
+
+    // Annotate the original image.
+    let start = Instant::now();
+    let annotated = annotate_image_with_bboxes(image, LEGEND_SIZE, &detections, labels);
+    frame_times.annotation = start.elapsed();
+
+    Ok((annotated, detections))
+}
+
+/// Runs only the tracker prediction step and annotation on the given `image`,
+/// for frames the detector is skipped on, see [Tracker::predict].
+///
+/// Returns the annotated image, along with the predicted bboxes drawn on it.
+pub fn predict_frame(
+    tracker: &mut dyn Tracker,
+    labels: &LabelMap,
+    image: DynamicImage,
+    timestamp: Option<Duration>,
+    frame_times: &mut FrameTimes,
+) -> (DynamicImage, Detections) {
+    let start = Instant::now();
+    let frame_dims = ImgDimensions::new(image.width() as f32, image.height() as f32);
+    let mut detections = tracker.predict(frame_dims, timestamp);
+    detections.timestamp = timestamp;
+    frame_times.tracking = start.elapsed();
+    log::debug!("{detections:?}");
+
+    let start = Instant::now();
+    let annotated = annotate_image_with_bboxes(image, LEGEND_SIZE, &detections, labels);
+    frame_times.annotation = start.elapsed();
+
+    (annotated, detections)
+}
+
+/// Reads the RGB video frame `buffer` of `frame_dims` into an image.
+fn buffer_to_image(frame_dims: ImgDimensions, buffer: &gst::Buffer) -> DynamicImage {
+    let readable = buffer.map_readable().unwrap();
+    let readable_vec = readable.to_vec();
+
+    // buffer size is: width x height x 3
+    let image = RgbImage::from_vec(
+        frame_dims.width as u32,
+        frame_dims.height as u32,
+        readable_vec,
+    )
+    .unwrap();
+    DynamicImage::ImageRgb8(image)
+}
+
+/// Overwrites the video frame `buffer` with the processed `image`.
+fn image_to_buffer(image: DynamicImage, buffer: &mut gst::Buffer) {
+    let mut writable = buffer.make_mut().map_writable().unwrap();
+    let mut dst = writable.as_mut_slice();
+    dst.write_all(image.to_rgb8().as_raw()).unwrap();
+}
+
+/// Input video, processed with its own tracker and exports, see [process_videos].
+pub struct VideoSource {
+    /// Video file path or stream URI.
+    pub input: PathBuf,
+    /// Tracker of this video, use [gstreamed_tracker::NoopTracker] to skip tracking.
+    pub tracker: Box<dyn Tracker>,
+    /// Writers of this video's detections.
+    pub exports: Exports,
+}
+
+/// [VideoSource] being processed.
+struct Stream {
+    source: VideoSource,
+    frame_dims: ImgDimensions,
+    /// Decoded frames, `None` once the source has ended.
+    frames: Option<Receiver<gst::Buffer>>,
+    /// Index of the next frame.
+    frame_index: u64,
+}
+
+impl Stream {
+    /// Stops taking frames from the source, and finishes encoding of its `pipeline`.
+    fn finish(&mut self, pipeline: &InferencePipeline) {
+        if self.frames.take().is_some() {
+            let _ = pipeline.end_of_stream();
+        }
+    }
+}
+
+/// Processes the `batch` of decoded frames, of [Stream] at each index of `streams`, in place.
+///
+/// Frames of streams due for detection, every `detect_every`-th frame, are detected in a single [Detector::detect_batch],
+/// then each frame is tracked by its stream's tracker (see [track_frame] and [predict_frame]) and annotated.
+/// Detections are also written into stream's exports.
+///
+/// Returns the [FrameTimes] of the whole batch.
+fn process_batch(
+    batch: &mut [(usize, gst::Buffer)],
+    streams: &mut [Stream],
+    detect_every: u64,
+    detector: &dyn Detector,
+    embedder: Option<&dyn Embedder>,
+) -> anyhow::Result<FrameTimes> {
+    let mut frame_times = FrameTimes::default();
+
+    let start = Instant::now();
+    let images: Vec<_> = batch
+        .iter()
+        .map(|(index, buffer)| buffer_to_image(streams[*index].frame_dims, buffer))
+        .collect();
+    frame_times.frame_to_buffer = start.elapsed();
+
+    // Run the detector on frames of all streams due for detection at once.
+    let detect: Vec<_> = batch
+        .iter()
+        .map(|(index, _)| streams[*index].frame_index.is_multiple_of(detect_every))
+        .collect();
+    let to_detect: Vec<_> = images
+        .iter()
+        .zip(&detect)
+        .filter_map(|(image, detect)| detect.then_some(image))
+        .collect();
+    let mut detected = if to_detect.is_empty() {
+        vec![]
+    } else {
+        let (detected, detector_times) = detector.detect_batch(&to_detect)?;
+        add_detector_times(&mut frame_times, &detector_times);
+        detected
+    }
+    .into_iter();
+
+    for (((index, buffer), image), detect) in batch.iter_mut().zip(images).zip(detect) {
+        let stream = &mut streams[*index];
+        let mut image_times = FrameTimes::default();
+        let timestamp = buffer.pts().map(Duration::from);
+        let (processed, detections) = if detect {
+            let detections = detected.next().unwrap();
+            track_frame(
+                stream.source.tracker.as_mut(),
+                embedder,
+                detector.labels(),
+                image,
+                detections,
+                timestamp,
+                &mut image_times,
+            )?
+        } else {
+            predict_frame(
+                stream.source.tracker.as_mut(),
+                detector.labels(),
+                image,
+                timestamp,
+                &mut image_times,
+            )
+        };
+        frame_times.tracking += image_times.tracking;
+        frame_times.annotation += image_times.annotation;
+
+        stream
+            .source
+            .exports
+            .write_frame(stream.frame_index, &detections, detector.labels())?;
+        stream.frame_index += 1;
+
+        // overwrite the buffer with our overlaid processed image
+        let start = Instant::now();
+        image_to_buffer(processed, buffer);
+        frame_times.buffer_to_frame += start.elapsed();
+    }
+
+    Ok(frame_times)
+}
+
+/// Collects the next frame of each running stream into a batch, see [process_batch], until all streams end.
+///
+/// Frames are taken from streams in turn, so a stream that's behind holds up the others.
+/// Processed frames are pushed back into each stream's pipeline for encoding.
+fn process_streams(
+    pipelines: &[InferencePipeline],
+    streams: &mut [Stream],
+    detect_every: u64,
+    detector: &dyn Detector,
+    embedder: Option<&dyn Embedder>,
+    agg_times: &mut AggregatedTimes,
+) -> anyhow::Result<()> {
+    loop {
+        let mut batch = Vec::with_capacity(streams.len());
+        for (index, stream) in streams.iter_mut().enumerate() {
+            let Some(frames) = &stream.frames else {
+                continue;
+            };
+            match frames.recv() {
+                Ok(buffer) => batch.push((index, buffer)),
+                // Decoding has finished (or failed), finish encoding as well.
+                Err(_) => stream.finish(&pipelines[index]),
+            }
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let frame_times = process_batch(&mut batch, streams, detect_every, detector, embedder)?;
+        log::debug!("{frame_times:?}");
+        agg_times.push(frame_times);
+
+        for (index, buffer) in batch {
+            if let Err(err) = pipelines[index].push(buffer) {
+                log::error!(
+                    "Failed to push processed frame of {:?}: {err:?}",
+                    streams[index].source.input
+                );
+                streams[index].finish(&pipelines[index]);
+            }
+        }
+    }
+}
+
+/// Performs inference on video files of `sources`, using a gstreamer pipeline per source + the given `detector`.
+///
+/// Frames of all sources are detected together, in a single batch, see [Detector::detect_batch].
+/// Detections are tracked across frames by the tracker of each source,
+/// with appearance features from `embedder`, if given.
+/// The detector only runs on every `detect_every`-th frame, tracks are predicted on the frames in between.
+/// Per-frame detections are also written into exports of each source, and each gets its own annotated output.
+///
+/// Frames are processed on a separate thread, while the pipelines keep decoding and encoding,
+/// see [InferencePipeline]. Frames of live sources are dropped while processing can't keep up.
+pub fn process_videos(
+    sources: Vec<VideoSource>,
+    live_playback: bool,
+    detect_every: u64,
+    detector: Box<dyn Detector>,
+    embedder: Option<Box<dyn Embedder>>,
+) -> anyhow::Result<()> {
+    gst::init()?;
+
+    let mut agg_times = AggregatedTimes::default();
+
+    let mut pipelines = Vec::with_capacity(sources.len());
+    let mut streams = Vec::with_capacity(sources.len());
+    for source in sources {
+        // First, find out resolution of input file.
+        log::info!("Discovering media properties of {:?}", source.input);
+        let file_info = discovery::discover(&source.input)?;
+        log::info!("{file_info:?}");
+        let frame_dims = ImgDimensions::new(file_info.width as f32, file_info.height as f32);
+
+        // Build gst pipeline, frames are handed over to us for inference.
+        pipelines.push(build_pipeline(
+            source.input.to_str().unwrap(),
+            live_playback,
+        )?);
+        streams.push(Stream {
+            source,
+            frame_dims,
+            frames: None,
+            frame_index: 0,
+        });
+    }
+
+    // Make them play and listen to events to know when they're done.
+    log::info!("Starting {} gst pipeline(s)", pipelines.len());
+    for (pipeline, stream) in pipelines.iter().zip(&mut streams) {
+        stream.frames = Some(pipeline.play()?);
+    }
+
+    let inputs: Vec<_> = streams.iter().map(|s| s.source.input.clone()).collect();
+    let result = thread::scope(|scope| {
+        for (pipeline, input) in pipelines.iter().zip(&inputs) {
+            scope.spawn(move || {
+                match pipeline.wait() {
+                    Ok(()) => log::info!("Pipeline of {input:?} reached end of stream."),
+                    Err(err) => log::error!("Pipeline of {input:?} failed: {err}"),
+                }
+                // Stopping also unblocks processing, if it's waiting on the pipeline after an error.
+                pipeline.stop().unwrap();
+            });
+        }
+
+        let result = process_streams(
+            &pipelines,
+            &mut streams,
+            detect_every,
+            detector.as_ref(),
+            embedder.as_deref(),
+            &mut agg_times,
+        );
+        if let Err(err) = &result {
+            // Drop remaining frames, so decoding doesn't wait for them to be processed.
+            for stream in &mut streams {
+                stream.frames = None;
+            }
+            for pipeline in &pipelines {
+                pipeline.abort(&format!("Processing failed: {err}"));
+            }
+        }
+        result
+    });
+
+    for stream in &mut streams {
+        stream.source.tracker.finish();
+        stream.source.exports.flush()?;
+    }
+    result?;
+
+    // Print perf stats, ignoring first (outlier) frame.
+    log_frame_time_stats(&agg_times);
+
+    Ok(())
+}
+
+/// Logs average, min and max frame times, ignoring the first (outlier) frame.
+pub fn log_frame_time_stats(agg: &AggregatedTimes) {
+    let avg = agg.avg(true);
+    log::info!("Average frame times: {avg:?}");
+
+    let min = agg.min(true);
+    log::info!("Min frame times: {min:?}");
+
+    let max = agg.max(true);
+    log::info!("Max frame times: {max:?}");
+}
@@ -1,26 +1,23 @@
 //! Roughly corresponds to the logic required for `report_detect` function
 //! in yolov8 example code in candle repo.
 
-use std::io::Write;
-use std::path::PathBuf;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use candle_core::{DType, Device, IndexOp, Module, Tensor};
 use candle_nn::VarBuilder;
 use clap::ValueEnum;
-use gstreamed_common::bbox::{non_maximum_suppression, Bbox};
-use gstreamed_common::frame_times::AggregatedTimes;
+use gstreamed_common::bbox::{non_maximum_suppression, Bbox, Detections};
+use gstreamed_common::detector::Detector;
+use gstreamed_common::frame_times::FrameTimes;
 use gstreamed_common::img_dimensions::ImgDimensions;
-use gstreamed_common::{annotate::annotate_image_with_bboxes, frame_times::FrameTimes};
-use gstreamed_tracker::similari::prelude::Sort;
-use gstreamed_tracker::unflatten_bboxes;
-use gstreamer as gst;
-use image::{DynamicImage, RgbImage};
+use gstreamed_common::labels::LabelMap;
+use gstreamed_common::letterbox::letterbox;
+use image::DynamicImage;
 
 use crate::yolov8::{Multiples, YoloV8};
 
-// TODO move this to args
+/// Yolov8 model size variant.
 #[derive(Clone, Copy, ValueEnum, Debug)]
 pub enum Which {
     N,
@@ -45,7 +42,15 @@ fn model(which: Which) -> anyhow::Result<PathBuf> {
     Ok(path)
 }
 
-pub fn load_model(which: Which, device: &Device) -> anyhow::Result<YoloV8> {
+/// Loads yolov8 model of the given `which` size, detecting `num_classes` classes.
+///
+/// Safetensors `weights` are loaded from the given path if specified, otherwise downloaded from hf hub.
+pub fn load_model(
+    which: Which,
+    weights: Option<&Path>,
+    num_classes: usize,
+    device: &Device,
+) -> anyhow::Result<YoloV8> {
     let multiples = match which {
         Which::N => Multiples::n(),
         Which::S => Multiples::s(),
@@ -53,27 +58,31 @@ pub fn load_model(which: Which, device: &Device) -> anyhow::Result<YoloV8> {
         Which::L => Multiples::l(),
         Which::X => Multiples::x(),
     };
-    let model = model(which)?;
+    let model = match weights {
+        Some(weights) => weights.to_path_buf(),
+        None => model(which)?,
+    };
+    log::info!("Loading candle yolov8 model from: {model:?}");
     let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[model], DType::F32, device)? };
     // let weights = unsafe { candle_core::safetensors::MmapedFile::new(model)? };
     // let weights = weights.deserialize()?;
     // let vb = VarBuilder::from_safetensors(vec![weights], DType::F32, &Device::Cpu);
-    let model = YoloV8::load(vb, multiples, 80)?;
+    let model = YoloV8::load(vb, multiples, num_classes)?;
     Ok(model)
 }
 
 fn post_process_preds(
     pred: &Tensor,
+    scaled_dims: ImgDimensions,
     confidence_threshold: f32,
     nms_threshold: f32,
     frame_times: &mut FrameTimes,
-) -> anyhow::Result<Vec<Vec<Bbox>>> {
+) -> anyhow::Result<Detections> {
     // println!("initial pred.shape: {:?}", pred.shape());
     let start = Instant::now();
     let (pred_size, npreds) = pred.dims2()?;
     let nclasses = pred_size - 4;
-    // The bounding boxes grouped by (maximum) class index.
-    let mut bboxes: Vec<Vec<Bbox>> = (0..nclasses).map(|_| vec![]).collect();
+    let mut detections = Detections::new(scaled_dims);
     // Extract the bounding boxes for which confidence is above the threshold.
     // Since we compute bboxes on cpu, transfer whole prediction tensor to cpu, so it's not done inside a loop.
     let pred = pred.to_device(&Device::Cpu)?.to_dtype(DType::F32)?;
@@ -102,148 +111,119 @@ fn post_process_preds(
                     data: vec![],
                     class: class_index,
                     tracker_id: None,
+                    observed: None,
+                    predicted: false,
                 };
-                bboxes[class_index].push(bbox)
+                detections.push(bbox)
             }
         }
     }
     frame_times.bbox_extraction = start.elapsed();
 
     let start = Instant::now();
-    non_maximum_suppression(&mut bboxes, nms_threshold);
+    non_maximum_suppression(&mut detections, nms_threshold);
     frame_times.nms = start.elapsed();
-    Ok(bboxes)
+    Ok(detections)
 }
 
-/// Run yolov8 inference, and draw detections on top of the frame.
-///
-/// Largely copypasta of report_detect in candle yolov8 example code.
-#[allow(clippy::too_many_arguments)]
-pub fn process_frame(
-    frame: DynamicImage,
-    model: &YoloV8,
-    device: &Device,
-    tracker: &mut Sort,
-    conf_thresh: f32,
-    nms_thresh: f32,
-    legend_size: u32,
-    frame_times: &mut FrameTimes,
-) -> anyhow::Result<DynamicImage> {
-    // Resize buffer to match input size of model.
-    let start = Instant::now();
-    let (scaled_width, scaled_height) = {
-        let w = frame.width() as usize;
-        let h = frame.height() as usize;
-        if w < h {
-            let w = w * 640 / h;
-            // Sizes have to be divisible by 32.
-            (w / 32 * 32, 640)
-        } else {
-            let h = h * 640 / w;
-            (640, h / 32 * 32)
-        }
-    };
-    log::debug!("scaled w: {scaled_width}, scaled h: {scaled_height}");
-    let scaled_img = frame.resize_exact(
-        scaled_width as u32,
-        scaled_height as u32,
-        image::imageops::FilterType::Nearest,
-        // image::imageops::FilterType::CatmullRom,
-    );
-    frame_times.buffer_resize = start.elapsed();
-
-    // Convert image buffer to tensor.
-    let start = Instant::now();
-    let data = scaled_img.into_rgb8().into_raw();
-    let image_t =
-        Tensor::from_vec(data, (scaled_height, scaled_width, 3), device)?.permute((2, 0, 1))?;
-    let image_t = (image_t.unsqueeze(0)?.to_dtype(DType::F32)? * (1. / 255.))?;
-    frame_times.buffer_to_tensor = start.elapsed();
-
-    // Run forward pass.
-    let start = Instant::now();
-    let predictions = model.forward(&image_t)?.squeeze(0)?;
-    frame_times.forward_pass = start.elapsed();
-
-    // Postprocess predictions into bboxes.
-    let bboxes_per_class = post_process_preds(&predictions, conf_thresh, nms_thresh, frame_times)?;
-
-    // Track bboxes.
-    let start = Instant::now();
-    let tracked_bboxes = gstreamed_tracker::predict_tracked_bboxes(
-        tracker,
-        ImgDimensions::new(scaled_width as f32, scaled_height as f32),
-        &bboxes_per_class,
-    );
-    frame_times.tracking = start.elapsed();
+/// Yolov8 [Detector] running via candle.
+pub struct CandleDetector {
+    model: YoloV8,
+    device: Device,
+    labels: LabelMap,
+    conf_threshold: f32,
+    nms_threshold: f32,
+}
 
-    // Unflatten tracked bboxes back into bboxes per class.
-    let bboxes_per_class = unflatten_bboxes(tracked_bboxes);
+impl CandleDetector {
+    pub fn new(
+        model: YoloV8,
+        device: Device,
+        labels: LabelMap,
+        conf_threshold: f32,
+        nms_threshold: f32,
+    ) -> Self {
+        Self {
+            model,
+            device,
+            labels,
+            conf_threshold,
+            nms_threshold,
+        }
+    }
 
-    // Annotate the original image and print boxes information.
-    let start = Instant::now();
-    let annotated = annotate_image_with_bboxes(
-        frame,
-        scaled_width,
-        scaled_height,
-        legend_size,
-        &bboxes_per_class,
-    );
-    frame_times.annotation = start.elapsed();
-
-    // Return processed image tensor.
-    Ok(annotated)
+    /// Loads yolov8 model (see [load_model]) onto cpu or `cuda` device and wraps it into a detector.
+    ///
+    /// Model is loaded with as many classes as there are `labels`.
+    pub fn load(
+        which: Which,
+        weights: Option<&Path>,
+        labels: LabelMap,
+        cuda: bool,
+        conf_threshold: f32,
+        nms_threshold: f32,
+    ) -> anyhow::Result<Self> {
+        let device = if cuda {
+            Device::new_cuda(0)?
+        } else {
+            Device::Cpu
+        };
+        let model = load_model(which, weights, labels.len(), &device)?;
+        Ok(Self::new(
+            model,
+            device,
+            labels,
+            conf_threshold,
+            nms_threshold,
+        ))
+    }
 }
 
-pub fn process_buffer(
-    frame_dims: ImgDimensions,
-    model: &YoloV8,
-    device: &Device,
-    tracker: &Mutex<Sort>,
-    agg_times: &mut AggregatedTimes,
-    buffer: &mut gst::Buffer,
-) {
-    let mut frame_times = FrameTimes::default();
+impl Detector for CandleDetector {
+    /// Run yolov8 inference on the given frame.
+    ///
+    /// Largely copypasta of report_detect in candle yolov8 example code.
+    fn detect(&self, frame: &DynamicImage) -> anyhow::Result<(Detections, FrameTimes)> {
+        let mut frame_times = FrameTimes::default();
+
+        // Letterbox buffer to match input size of model.
+        // Sizes have to be divisible by 32, so we pad the frame only as much as necessary for that.
+        let start = Instant::now();
+        let (scaled_img, transform) = letterbox(frame, ImgDimensions::new(640.0, 640.0), Some(32))?;
+        let (scaled_width, scaled_height) =
+            (scaled_img.width() as usize, scaled_img.height() as usize);
+        log::debug!("scaled w: {scaled_width}, scaled h: {scaled_height}");
+        frame_times.buffer_resize = start.elapsed();
+
+        // Convert image buffer to tensor.
+        let start = Instant::now();
+        let data = scaled_img.into_raw();
+        let image_t = Tensor::from_vec(data, (scaled_height, scaled_width, 3), &self.device)?
+            .permute((2, 0, 1))?;
+        let image_t = (image_t.unsqueeze(0)?.to_dtype(DType::F32)? * (1. / 255.))?;
+        frame_times.buffer_to_tensor = start.elapsed();
+
+        // Run forward pass.
+        let start = Instant::now();
+        let predictions = self.model.forward(&image_t)?.squeeze(0)?;
+        frame_times.forward_pass = start.elapsed();
+
+        // Postprocess predictions into bboxes.
+        let mut detections = post_process_preds(
+            &predictions,
+            transform.letterboxed_dims,
+            self.conf_threshold,
+            self.nms_threshold,
+            &mut frame_times,
+        )?;
+
+        // Map bboxes back to the original frame coordinates.
+        transform.to_original(&mut detections);
+
+        Ok((detections, frame_times))
+    }
 
-    let start = Instant::now();
-    // read buffer into an image
-    let image = {
-        let readable = buffer.map_readable().unwrap();
-        let readable_vec = readable.to_vec();
-
-        // buffer size is: width x height x 3
-        let image = RgbImage::from_vec(
-            frame_dims.width as u32,
-            frame_dims.height as u32,
-            readable_vec,
-        )
-        .unwrap();
-        DynamicImage::ImageRgb8(image)
-    };
-    frame_times.frame_to_buffer = start.elapsed();
-
-    // process it using some model + draw overlays on the output image
-    let mut tracker = tracker.lock().unwrap();
-    let processed = process_frame(
-        image,
-        model,
-        device,
-        &mut tracker,
-        0.25,
-        0.45,
-        14,
-        &mut frame_times,
-    )
-    .unwrap();
-
-    // Overwrite the buffer with our overlaid processed image.
-    let start = Instant::now();
-    let buffer_mut = buffer.get_mut().unwrap();
-    let mut writable = buffer_mut.map_writable().unwrap();
-    let mut dst = writable.as_mut_slice();
-    dst.write_all(processed.to_rgb8().as_raw()).unwrap();
-    frame_times.buffer_to_frame = start.elapsed();
-
-    log::debug!("{frame_times:?}");
-    agg_times.push(frame_times);
+    fn labels(&self) -> &LabelMap {
+        &self.labels
+    }
 }
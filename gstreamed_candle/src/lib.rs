@@ -0,0 +1,6 @@
+//! Yolov8 object detection via `candle` library.
+
+pub mod inference;
+pub mod yolov8;
+
+pub use inference::CandleDetector;
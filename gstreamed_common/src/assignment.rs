@@ -0,0 +1,99 @@
+//! Optimal assignment between two sets, e.g. ground truth and detected/tracked bboxes.
+
+/// Solves the linear assignment problem for the given rectangular `cost` matrix,
+/// using the Hungarian algorithm.
+///
+/// Returns `(row, col)` pairs that minimize total cost, every row or every column
+/// (whichever are fewer) is assigned exactly once.
+pub fn linear_assignment(cost: &[Vec<f64>]) -> Vec<(usize, usize)> {
+    let rows = cost.len();
+    let cols = cost.first().map(Vec::len).unwrap_or(0);
+    if rows == 0 || cols == 0 {
+        return vec![];
+    }
+    // The algorithm below requires rows <= cols, so transpose if needed.
+    if rows > cols {
+        let transposed: Vec<Vec<f64>> = (0..cols)
+            .map(|col| (0..rows).map(|row| cost[row][col]).collect())
+            .collect();
+        let mut pairs: Vec<_> = linear_assignment(&transposed)
+            .into_iter()
+            .map(|(col, row)| (row, col))
+            .collect();
+        pairs.sort_unstable();
+        return pairs;
+    }
+
+    // Shortest augmenting path with potentials, 1-based with 0 as a virtual column.
+    let mut u = vec![0.0; rows + 1];
+    let mut v = vec![0.0; cols + 1];
+    // Row assigned to each column, 0 if none.
+    let mut assigned = vec![0; cols + 1];
+    let mut way = vec![0; cols + 1];
+    for row in 1..=rows {
+        assigned[0] = row;
+        let mut col0 = 0;
+        let mut min_v = vec![f64::INFINITY; cols + 1];
+        let mut used = vec![false; cols + 1];
+        loop {
+            used[col0] = true;
+            let row0 = assigned[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+            for col in 1..=cols {
+                if used[col] {
+                    continue;
+                }
+                let cur = cost[row0 - 1][col - 1] - u[row0] - v[col];
+                if cur < min_v[col] {
+                    min_v[col] = cur;
+                    way[col] = col0;
+                }
+                if min_v[col] < delta {
+                    delta = min_v[col];
+                    col1 = col;
+                }
+            }
+            for col in 0..=cols {
+                if used[col] {
+                    u[assigned[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min_v[col] -= delta;
+                }
+            }
+            col0 = col1;
+            if assigned[col0] == 0 {
+                break;
+            }
+        }
+        // Flip the augmenting path.
+        while col0 != 0 {
+            let col1 = way[col0];
+            assigned[col0] = assigned[col1];
+            col0 = col1;
+        }
+    }
+
+    let mut pairs: Vec<_> = (1..=cols)
+        .filter(|&col| assigned[col] != 0)
+        .map(|col| (assigned[col] - 1, col - 1))
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+#[test]
+fn assignment_minimizes_total_cost() {
+    let cost = vec![
+        vec![4.0, 1.0, 3.0],
+        vec![2.0, 0.0, 5.0],
+        vec![3.0, 2.0, 2.0],
+    ];
+    assert_eq!(linear_assignment(&cost), [(0, 1), (1, 0), (2, 2)]);
+
+    // More rows than columns, so the last row is left unassigned.
+    let cost = vec![vec![1.0, 9.0], vec![9.0, 1.0], vec![5.0, 5.0]];
+    assert_eq!(linear_assignment(&cost), [(0, 0), (1, 1)]);
+    assert!(linear_assignment(&[]).is_empty());
+}
@@ -1,5 +1,9 @@
 //! Lifted wholesale from candle-transformers to avoid candle-transformers dependency for ort version.
 
+use std::time::Duration;
+
+use crate::img_dimensions::ImgDimensions;
+
 /// A bounding box around an object.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Bbox {
@@ -12,6 +16,40 @@ pub struct Bbox {
     pub data: Vec<KeyPoint>,
     pub class: usize,
     pub tracker_id: Option<i64>,
+    /// Detected `[xmin, ymin, xmax, ymax]` bbox, which updated the track on this frame,
+    /// while the coordinates above are smoothed by the tracker.
+    /// `None` for untracked detections and tracker predictions.
+    pub observed: Option<[f32; 4]>,
+    /// Whether the bbox was predicted by the tracker, on a frame without detection.
+    pub predicted: bool,
+}
+
+impl Bbox {
+    /// Clamps bbox coordinates to the frame with `frame_dims`.
+    pub fn clamp(&mut self, frame_dims: ImgDimensions) {
+        self.xmin = self.xmin.max(0.0).min(frame_dims.width);
+        self.ymin = self.ymin.max(0.0).min(frame_dims.height);
+        self.xmax = self.xmax.max(0.0).min(frame_dims.width);
+        self.ymax = self.ymax.max(0.0).min(frame_dims.height);
+    }
+
+    /// Untracked detection of `class` with 0.9 confidence, for tests.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn test(xmin: f32, ymin: f32, xmax: f32, ymax: f32, class: usize) -> Self {
+        Self {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+            detector_confidence: 0.9,
+            tracker_confidence: 0.0,
+            data: vec![],
+            class,
+            tracker_id: None,
+            observed: None,
+            predicted: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -33,29 +71,202 @@ pub fn iou(b1: &Bbox, b2: &Bbox) -> f32 {
     i_area / (b1_area + b2_area - i_area)
 }
 
-pub fn non_maximum_suppression(bboxes: &mut [Vec<Bbox>], threshold: f32) {
-    // Perform non-maximum suppression.
-    for bboxes_for_class in bboxes.iter_mut() {
-        bboxes_for_class.sort_by(|b1, b2| {
-            b2.detector_confidence
-                .partial_cmp(&b1.detector_confidence)
-                .unwrap()
-        });
-        let mut current_index = 0;
-        for index in 0..bboxes_for_class.len() {
-            let mut drop = false;
-            for prev_index in 0..current_index {
-                let iou = iou(&bboxes_for_class[prev_index], &bboxes_for_class[index]);
-                if iou > threshold {
-                    drop = true;
-                    break;
-                }
-            }
-            if !drop {
-                bboxes_for_class.swap(current_index, index);
-                current_index += 1;
+/// Bboxes detected (or tracked) on a single frame.
+///
+/// Bboxes are stored flat, in no particular order, per class views are available via [Detections::for_class].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detections {
+    bboxes: Vec<Bbox>,
+    /// Dimensions of the frame, in whose pixel coordinates the bboxes are.
+    pub frame_dims: ImgDimensions,
+    /// Presentation timestamp of the frame, if known.
+    pub timestamp: Option<Duration>,
+}
+
+impl Detections {
+    /// Creates an empty collection for a frame of the given dimensions.
+    pub fn new(frame_dims: ImgDimensions) -> Self {
+        Self::from_bboxes(Vec::new(), frame_dims)
+    }
+
+    pub fn from_bboxes(bboxes: Vec<Bbox>, frame_dims: ImgDimensions) -> Self {
+        Self {
+            bboxes,
+            frame_dims,
+            timestamp: None,
+        }
+    }
+
+    pub fn push(&mut self, bbox: Bbox) {
+        self.bboxes.push(bbox);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bboxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bboxes.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Bbox> {
+        self.bboxes.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Bbox> {
+        self.bboxes.iter_mut()
+    }
+
+    pub fn as_slice(&self) -> &[Bbox] {
+        &self.bboxes
+    }
+
+    pub fn into_vec(self) -> Vec<Bbox> {
+        self.bboxes
+    }
+
+    /// Bboxes of the given `class`.
+    pub fn for_class(&self, class: usize) -> impl Iterator<Item = &Bbox> {
+        self.bboxes.iter().filter(move |bbox| bbox.class == class)
+    }
+
+    /// Distinct classes present, in ascending order.
+    pub fn classes(&self) -> Vec<usize> {
+        let mut classes: Vec<_> = self.bboxes.iter().map(|bbox| bbox.class).collect();
+        classes.sort_unstable();
+        classes.dedup();
+        classes
+    }
+
+    /// Keeps only the bboxes for which `f` returns `true`.
+    pub fn retain(&mut self, f: impl FnMut(&Bbox) -> bool) {
+        self.bboxes.retain(f);
+    }
+
+    /// Returns a new collection for the same frame with only the bboxes for which `f` returns `true`.
+    pub fn filter(&self, mut f: impl FnMut(&Bbox) -> bool) -> Self {
+        Self {
+            bboxes: self.bboxes.iter().filter(|bbox| f(bbox)).cloned().collect(),
+            frame_dims: self.frame_dims,
+            timestamp: self.timestamp,
+        }
+    }
+
+    /// Returns a new collection for the same frame with the given `bboxes`.
+    pub fn with_bboxes(&self, bboxes: Vec<Bbox>) -> Self {
+        Self {
+            bboxes,
+            frame_dims: self.frame_dims,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl IntoIterator for Detections {
+    type Item = Bbox;
+    type IntoIter = std::vec::IntoIter<Bbox>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.bboxes.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Detections {
+    type Item = &'a Bbox;
+    type IntoIter = std::slice::Iter<'a, Bbox>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.bboxes.iter()
+    }
+}
+
+/// Performs per class non-maximum suppression.
+///
+/// Bboxes with non-finite confidence, e.g. from a broken model output, are dropped.
+pub fn non_maximum_suppression(detections: &mut Detections, threshold: f32) {
+    let bboxes = &mut detections.bboxes;
+    bboxes.retain(|bbox| bbox.detector_confidence.is_finite());
+    // Group by class, then order by descending confidence within each class.
+    bboxes.sort_by(|b1, b2| {
+        b1.class
+            .cmp(&b2.class)
+            .then(b2.detector_confidence.total_cmp(&b1.detector_confidence))
+    });
+    // Kept bboxes are moved to the front, `class_start..current_index` are the ones kept for the current class.
+    let mut current_index = 0;
+    let mut class_start = 0;
+    let mut current_class = None;
+    for index in 0..bboxes.len() {
+        if current_class != Some(bboxes[index].class) {
+            current_class = Some(bboxes[index].class);
+            class_start = current_index;
+        }
+        let mut drop = false;
+        for prev_index in class_start..current_index {
+            let iou = iou(&bboxes[prev_index], &bboxes[index]);
+            if iou > threshold {
+                drop = true;
+                break;
             }
         }
-        bboxes_for_class.truncate(current_index);
+        if !drop {
+            bboxes.swap(current_index, index);
+            current_index += 1;
+        }
     }
+    bboxes.truncate(current_index);
+}
+
+#[test]
+fn nms_is_per_class() {
+    let mut detections = Detections::from_bboxes(
+        vec![
+            Bbox {
+                detector_confidence: 0.5,
+                ..Bbox::test(0.0, 0.0, 10.0, 10.0, 0)
+            },
+            Bbox::test(1.0, 1.0, 10.0, 10.0, 0),
+            Bbox {
+                detector_confidence: 0.8,
+                ..Bbox::test(0.0, 0.0, 10.0, 10.0, 1)
+            },
+            Bbox {
+                detector_confidence: 0.3,
+                ..Bbox::test(50.0, 50.0, 60.0, 60.0, 0)
+            },
+        ],
+        ImgDimensions::new(100.0, 100.0),
+    );
+    non_maximum_suppression(&mut detections, 0.45);
+
+    assert_eq!(detections.len(), 3);
+    let class0: Vec<_> = detections
+        .for_class(0)
+        .map(|b| b.detector_confidence)
+        .collect();
+    assert_eq!(class0, vec![0.9, 0.3]);
+    assert_eq!(detections.for_class(1).count(), 1);
+    assert_eq!(detections.classes(), vec![0, 1]);
+}
+
+#[test]
+fn nms_drops_non_finite_confidences() {
+    let mut detections = Detections::from_bboxes(
+        vec![
+            Bbox {
+                detector_confidence: f32::NAN,
+                ..Bbox::test(0.0, 0.0, 10.0, 10.0, 0)
+            },
+            Bbox::test(1.0, 1.0, 10.0, 10.0, 0),
+            Bbox {
+                detector_confidence: f32::INFINITY,
+                ..Bbox::test(50.0, 50.0, 60.0, 60.0, 0)
+            },
+        ],
+        ImgDimensions::new(100.0, 100.0),
+    );
+    non_maximum_suppression(&mut detections, 0.45);
+
+    let confidences: Vec<_> = detections.iter().map(|b| b.detector_confidence).collect();
+    assert_eq!(confidences, vec![0.9]);
 }
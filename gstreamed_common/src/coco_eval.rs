@@ -0,0 +1,452 @@
+//! COCO style detection evaluation: mAP@0.5, mAP@0.5:0.95 and per-class AP.
+//!
+//! Follows `pycocotools` `COCOeval` for bbox evaluation, with the "all" area range
+//! and up to [MAX_DETECTIONS] per image, but uses our [iou] for box overlaps.
+//! Overlaps with crowd regions are measured relative to the detection area, as in `pycocotools`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bbox::{iou, Bbox, Detections};
+use crate::labels::LabelMap;
+
+/// Maximum number of (most confident) detections per image that are evaluated, of all categories.
+pub const MAX_DETECTIONS: usize = 100;
+
+/// IoU thresholds 0.5:0.05:0.95, AP is averaged over them for mAP@0.5:0.95.
+pub const IOU_THRESHOLDS: [f32; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+
+/// Number of recall points precision is sampled at.
+const RECALL_POINTS: usize = 101;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CocoImage {
+    pub id: u64,
+    pub file_name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CocoAnnotation {
+    pub image_id: u64,
+    pub category_id: u64,
+    /// `[left, top, width, height]` in pixels.
+    pub bbox: [f32; 4],
+    #[serde(default)]
+    pub iscrowd: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CocoCategory {
+    pub id: u64,
+    pub name: String,
+}
+
+/// Images, annotations and categories of a COCO `instances_*.json` annotation file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CocoDataset {
+    pub images: Vec<CocoImage>,
+    pub annotations: Vec<CocoAnnotation>,
+    pub categories: Vec<CocoCategory>,
+}
+
+impl CocoDataset {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).map_err(|err| anyhow::anyhow!("Failed to open {path:?}: {err}"))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| anyhow::anyhow!("Failed to parse COCO annotations {path:?}: {err}"))
+    }
+}
+
+/// A single detection in COCO results format.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CocoResult {
+    pub image_id: u64,
+    pub category_id: u64,
+    /// `[left, top, width, height]` in pixels.
+    pub bbox: [f32; 4],
+    pub score: f32,
+}
+
+/// Maps our class ids to COCO category ids and back, matching them by name.
+#[derive(Debug, Clone)]
+pub struct CategoryMap {
+    /// COCO category id for each class id, if there is one.
+    categories: Vec<Option<u64>>,
+}
+
+impl CategoryMap {
+    /// Matches `categories` to `labels` by name, failing if none of them match.
+    pub fn new(categories: &[CocoCategory], labels: &LabelMap) -> anyhow::Result<Self> {
+        let mut map = vec![None; labels.len()];
+        for category in categories {
+            match labels.class_id(&category.name) {
+                Some(class) => map[class] = Some(category.id),
+                None => log::warn!(
+                    "COCO category {:?} (id {}) is not in the label map, it won't be evaluated",
+                    category.name,
+                    category.id
+                ),
+            }
+        }
+        anyhow::ensure!(
+            map.iter().any(Option::is_some),
+            "None of the COCO categories match the label map"
+        );
+        Ok(Self { categories: map })
+    }
+
+    pub fn category(&self, class: usize) -> Option<u64> {
+        self.categories.get(class).copied().flatten()
+    }
+
+    pub fn class(&self, category: u64) -> Option<usize> {
+        self.categories.iter().position(|&id| id == Some(category))
+    }
+}
+
+/// Ground truth bbox, crowd annotations are regions where detections are ignored.
+#[derive(Debug, Clone)]
+struct GroundTruth {
+    bbox: Bbox,
+    crowd: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ImageEval {
+    ground_truth: Vec<GroundTruth>,
+    detections: Vec<Bbox>,
+}
+
+/// AP of a single class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassAp {
+    pub class: usize,
+    /// AP@0.5.
+    pub ap50: f32,
+    /// AP averaged over [IOU_THRESHOLDS].
+    pub ap50_95: f32,
+}
+
+/// Evaluation results, classes without any ground truth are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    pub map50: f32,
+    pub map50_95: f32,
+    pub per_class: Vec<ClassAp>,
+}
+
+/// Accumulates detections per image and evaluates them against the ground truth.
+pub struct CocoEvaluator {
+    categories: CategoryMap,
+    images: BTreeMap<u64, ImageEval>,
+}
+
+/// Overlap of `det` with a crowd `region`, intersection over the detection area,
+/// so a detection of a single object inside the region matches it.
+fn crowd_overlap(det: &Bbox, region: &Bbox) -> f32 {
+    let det_area = (det.xmax - det.xmin + 1.) * (det.ymax - det.ymin + 1.);
+    let i_width = det.xmax.min(region.xmax) - det.xmin.max(region.xmin) + 1.;
+    let i_height = det.ymax.min(region.ymax) - det.ymin.max(region.ymin) + 1.;
+    i_width.max(0.) * i_height.max(0.) / det_area
+}
+
+/// Converts COCO `[left, top, width, height]` into our [Bbox].
+fn ltwh_to_bbox([left, top, width, height]: [f32; 4], class: usize, confidence: f32) -> Bbox {
+    Bbox {
+        xmin: left,
+        ymin: top,
+        xmax: left + width,
+        ymax: top + height,
+        detector_confidence: confidence,
+        tracker_confidence: 0.0,
+        data: vec![],
+        class,
+        tracker_id: None,
+        observed: None,
+        predicted: false,
+    }
+}
+
+impl CocoEvaluator {
+    /// Prepares ground truth of the `dataset`, annotations of unmapped categories are skipped.
+    pub fn new(dataset: &CocoDataset, categories: CategoryMap) -> Self {
+        let mut images: BTreeMap<u64, ImageEval> = dataset
+            .images
+            .iter()
+            .map(|image| (image.id, ImageEval::default()))
+            .collect();
+        for annotation in &dataset.annotations {
+            let Some(class) = categories.class(annotation.category_id) else {
+                continue;
+            };
+            images
+                .entry(annotation.image_id)
+                .or_default()
+                .ground_truth
+                .push(GroundTruth {
+                    bbox: ltwh_to_bbox(annotation.bbox, class, 1.0),
+                    crowd: annotation.iscrowd != 0,
+                });
+        }
+        Self { categories, images }
+    }
+
+    /// Adds `detections` of the image with `image_id`.
+    ///
+    /// All of them are kept for [CocoEvaluator::results], only the [MAX_DETECTIONS] most confident ones
+    /// are evaluated.
+    pub fn add_detections(&mut self, image_id: u64, detections: &Detections) {
+        let mut bboxes: Vec<_> = detections
+            .iter()
+            .filter(|bbox| self.categories.category(bbox.class).is_some())
+            .cloned()
+            .collect();
+        bboxes.sort_by(|a, b| b.detector_confidence.total_cmp(&a.detector_confidence));
+        self.images.entry(image_id).or_default().detections = bboxes;
+    }
+
+    /// Detections added so far, in COCO results format.
+    pub fn results(&self) -> Vec<CocoResult> {
+        let mut results = Vec::new();
+        for (&image_id, image) in &self.images {
+            for bbox in &image.detections {
+                results.push(CocoResult {
+                    image_id,
+                    // Only detections with a category are kept.
+                    category_id: self.categories.category(bbox.class).unwrap(),
+                    bbox: [
+                        bbox.xmin,
+                        bbox.ymin,
+                        bbox.xmax - bbox.xmin,
+                        bbox.ymax - bbox.ymin,
+                    ],
+                    score: bbox.detector_confidence,
+                });
+            }
+        }
+        results
+    }
+
+    /// Writes detections added so far into a COCO results JSON file at `path`.
+    pub fn write_results(&self, path: &Path) -> anyhow::Result<()> {
+        let file = File::create(path)
+            .map_err(|err| anyhow::anyhow!("Failed to create {path:?}: {err}"))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &self.results())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Average precision of `class` at the given `iou_threshold`,
+    /// `None` if there is no ground truth of the `class`.
+    fn average_precision(&self, class: usize, iou_threshold: f32) -> Option<f32> {
+        // (score, is true positive) of all non-ignored detections.
+        let mut scored = Vec::new();
+        let mut num_ground_truth = 0;
+        for image in self.images.values() {
+            // Crowd regions go last, so real objects are matched first.
+            let mut ground_truth: Vec<_> = image
+                .ground_truth
+                .iter()
+                .filter(|gt| gt.bbox.class == class)
+                .collect();
+            ground_truth.sort_by_key(|gt| gt.crowd);
+            num_ground_truth += ground_truth.iter().filter(|gt| !gt.crowd).count();
+
+            // Detections are already sorted by confidence, the cap applies before picking the class.
+            let mut matched = vec![false; ground_truth.len()];
+            let detections = image.detections.iter().take(MAX_DETECTIONS);
+            for det in detections.filter(|det| det.class == class) {
+                let mut best_iou = iou_threshold;
+                let mut best_match = None;
+                for (idx, gt) in ground_truth.iter().enumerate() {
+                    if matched[idx] && !gt.crowd {
+                        continue;
+                    }
+                    // Prefer real objects over crowd regions.
+                    if best_match.is_some_and(|m: usize| !ground_truth[m].crowd) && gt.crowd {
+                        break;
+                    }
+                    let iou = match gt.crowd {
+                        true => crowd_overlap(det, &gt.bbox),
+                        false => iou(det, &gt.bbox),
+                    };
+                    if iou < best_iou {
+                        continue;
+                    }
+                    best_iou = iou;
+                    best_match = Some(idx);
+                }
+                match best_match {
+                    Some(idx) => {
+                        matched[idx] = true;
+                        // Detections of crowd regions are ignored.
+                        if !ground_truth[idx].crowd {
+                            scored.push((det.detector_confidence, true));
+                        }
+                    }
+                    None => scored.push((det.detector_confidence, false)),
+                }
+            }
+        }
+        if num_ground_truth == 0 {
+            return None;
+        }
+
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut recall = Vec::with_capacity(scored.len());
+        let mut precision = Vec::with_capacity(scored.len());
+        let mut tp = 0;
+        for (idx, (_, is_tp)) in scored.iter().enumerate() {
+            tp += *is_tp as usize;
+            recall.push(tp as f32 / num_ground_truth as f32);
+            precision.push(tp as f32 / (idx + 1) as f32);
+        }
+        // Precision envelope, so it's monotonically decreasing with recall.
+        for idx in (1..precision.len()).rev() {
+            precision[idx - 1] = precision[idx - 1].max(precision[idx]);
+        }
+
+        // Sample precision at evenly spaced recall points 0:0.01:1.
+        let mut sum = 0.0;
+        for point in 0..RECALL_POINTS {
+            let r = point as f32 / (RECALL_POINTS - 1) as f32;
+            let idx = recall.partition_point(|&recall| recall < r);
+            sum += precision.get(idx).copied().unwrap_or(0.0);
+        }
+        Some(sum / RECALL_POINTS as f32)
+    }
+
+    /// Computes mAP@0.5, mAP@0.5:0.95 and per-class AP of the detections added so far.
+    pub fn evaluate(&self) -> EvalReport {
+        let mut per_class = Vec::new();
+        for class in 0..self.categories.categories.len() {
+            let aps: Option<Vec<f32>> = IOU_THRESHOLDS
+                .iter()
+                .map(|&threshold| self.average_precision(class, threshold))
+                .collect();
+            if let Some(aps) = aps {
+                per_class.push(ClassAp {
+                    class,
+                    ap50: aps[0],
+                    ap50_95: aps.iter().sum::<f32>() / aps.len() as f32,
+                });
+            }
+        }
+
+        let mean = |ap: fn(&ClassAp) -> f32| {
+            if per_class.is_empty() {
+                0.0
+            } else {
+                per_class.iter().map(ap).sum::<f32>() / per_class.len() as f32
+            }
+        };
+        EvalReport {
+            map50: mean(|class_ap| class_ap.ap50),
+            map50_95: mean(|class_ap| class_ap.ap50_95),
+            per_class,
+        }
+    }
+}
+
+#[test]
+fn coco_eval_matches_by_score_and_iou() {
+    let dataset: CocoDataset = serde_json::from_str(
+        r#"{
+            "images": [{"id": 1, "file_name": "a.jpg", "width": 640, "height": 480}],
+            "annotations": [
+                {"image_id": 1, "category_id": 1, "bbox": [0, 0, 99, 99]},
+                {"image_id": 1, "category_id": 1, "bbox": [200, 200, 99, 99]},
+                {"image_id": 1, "category_id": 3, "bbox": [300, 0, 99, 99], "iscrowd": 1}
+            ],
+            "categories": [{"id": 1, "name": "person"}, {"id": 3, "name": "car"}]
+        }"#,
+    )
+    .unwrap();
+    let labels = LabelMap::coco();
+    let categories = CategoryMap::new(&dataset.categories, &labels).unwrap();
+    assert_eq!(categories.category(2), Some(3));
+    let mut evaluator = CocoEvaluator::new(&dataset, categories);
+
+    let frame_dims = crate::img_dimensions::ImgDimensions::new(640.0, 480.0);
+    let detections = Detections::from_bboxes(
+        vec![
+            // Exact match for the first person.
+            ltwh_to_bbox([0.0, 0.0, 99.0, 99.0], 0, 0.9),
+            // False positive, more confident than the second person.
+            ltwh_to_bbox([400.0, 400.0, 50.0, 50.0], 0, 0.8),
+            // Shifted second person, IoU is ~0.54.
+            ltwh_to_bbox([230.0, 200.0, 99.0, 99.0], 0, 0.7),
+            // Car in a crowd region is ignored.
+            ltwh_to_bbox([300.0, 0.0, 99.0, 99.0], 2, 0.9),
+        ],
+        frame_dims,
+    );
+    evaluator.add_detections(1, &detections);
+
+    let report = evaluator.evaluate();
+    // No non-crowd car ground truth, so only persons are evaluated.
+    assert_eq!(report.per_class.len(), 1);
+    // Recall 0.5 at precision 1, then recall 1 at precision 2/3.
+    let expected_ap50 = (51.0 + 50.0 * 2.0 / 3.0) / 101.0;
+    assert!((report.map50 - expected_ap50).abs() < 1e-6);
+    // Above 0.5 IoU, the shifted person is a false positive.
+    let expected_ap50_95 = (expected_ap50 + 9.0 * 51.0 / 101.0) / 10.0;
+    assert!((report.map50_95 - expected_ap50_95).abs() < 1e-6);
+
+    let results = evaluator.results();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[1].category_id, 3);
+}
+
+#[test]
+fn coco_eval_caps_detections_per_image_and_ignores_crowd_regions() {
+    let dataset: CocoDataset = serde_json::from_str(
+        r#"{
+            "images": [{"id": 1, "file_name": "a.jpg", "width": 640, "height": 480}],
+            "annotations": [
+                {"image_id": 1, "category_id": 1, "bbox": [0, 0, 99, 99]},
+                {"image_id": 1, "category_id": 1, "bbox": [200, 0, 99, 99]},
+                {"image_id": 1, "category_id": 6, "bbox": [500, 300, 99, 99]},
+                {"image_id": 1, "category_id": 6, "bbox": [0, 200, 400, 270], "iscrowd": 1}
+            ],
+            "categories": [
+                {"id": 1, "name": "person"}, {"id": 3, "name": "car"}, {"id": 6, "name": "bus"}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let categories = CategoryMap::new(&dataset.categories, &LabelMap::coco()).unwrap();
+    let mut evaluator = CocoEvaluator::new(&dataset, categories);
+
+    // More than MAX_DETECTIONS confident cars, which push out the least confident person,
+    // but not the one ranked among them.
+    let mut bboxes: Vec<_> = (0..150)
+        .map(|idx| ltwh_to_bbox([600.0, 0.0, 20.0, 20.0], 2, 0.9 - idx as f32 * 0.001))
+        .collect();
+    bboxes.push(ltwh_to_bbox([0.0, 0.0, 99.0, 99.0], 0, 0.1));
+    bboxes.push(ltwh_to_bbox([200.0, 0.0, 99.0, 99.0], 0, 0.85));
+    // A bus inside the crowd region, with low IoU but fully covered, is ignored.
+    bboxes.push(ltwh_to_bbox([10.0, 250.0, 50.0, 50.0], 5, 0.95));
+    bboxes.push(ltwh_to_bbox([500.0, 300.0, 99.0, 99.0], 5, 0.9));
+    let frame_dims = crate::img_dimensions::ImgDimensions::new(640.0, 480.0);
+    evaluator.add_detections(1, &Detections::from_bboxes(bboxes, frame_dims));
+
+    let report = evaluator.evaluate();
+    let aps: Vec<_> = report
+        .per_class
+        .iter()
+        .map(|class_ap| (class_ap.class, class_ap.ap50))
+        .collect();
+    // Recall 0.5 at precision 1 for persons.
+    assert_eq!(aps, vec![(0, 51.0 / 101.0), (5, 1.0)]);
+    // All detections are written into results.
+    assert_eq!(evaluator.results().len(), 154);
+}
@@ -0,0 +1,41 @@
+//! Common interface for object detection backends.
+
+use image::DynamicImage;
+
+use crate::bbox::Detections;
+use crate::frame_times::FrameTimes;
+use crate::labels::LabelMap;
+
+/// An object detector, such as yolov8 running via `ort` or `candle`.
+///
+/// Backends are interchangeable: given the same image, each returns [Detections]
+/// in the pixel coordinates of the input `image`, so tracking and annotation
+/// code does not need to know which backend produced them.
+pub trait Detector: Send + Sync {
+    /// Runs detection on the given `image`.
+    ///
+    /// Returns the detected bboxes, along with the [FrameTimes]
+    /// of the stages the detector ran (resize, tensor conversion, forward pass, bbox extraction, nms).
+    fn detect(&self, image: &DynamicImage) -> anyhow::Result<(Detections, FrameTimes)>;
+
+    /// Runs detection on a batch of `images`, returning [Detections] of each, in the same order.
+    ///
+    /// [FrameTimes] are of the whole batch. By default, images are detected one by one,
+    /// backends that can run the whole batch in a single forward pass override this.
+    fn detect_batch(
+        &self,
+        images: &[&DynamicImage],
+    ) -> anyhow::Result<(Vec<Detections>, FrameTimes)> {
+        let mut batch = Vec::with_capacity(images.len());
+        let mut batch_times = Vec::with_capacity(images.len());
+        for image in images {
+            let (detections, frame_times) = self.detect(image)?;
+            batch.push(detections);
+            batch_times.push(frame_times);
+        }
+        Ok((batch, batch_times.into_iter().sum()))
+    }
+
+    /// Names of the classes this detector detects.
+    fn labels(&self) -> &LabelMap;
+}
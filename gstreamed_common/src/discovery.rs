@@ -1,3 +1,4 @@
+use crate::pipeline::is_uri;
 use gstreamer as gst;
 use gstreamer::prelude::Displayable;
 use gstreamer_pbutils::prelude::DiscovererStreamInfoExt;
@@ -97,9 +98,14 @@ fn discover_resolution(info: &DiscovererInfo) -> anyhow::Result<FileInfo> {
 fn raw_discoverer_info(path: &Path) -> anyhow::Result<DiscovererInfo> {
     let timeout = gst::ClockTime::from_seconds(10);
     let discoverer = Discoverer::new(timeout)?;
-    // we need to pass absolute path to discoverer as file uri
-    let file_uri = format!("file://{}", path.canonicalize()?.to_str().unwrap());
-    Ok(discoverer.discover_uri(&file_uri)?)
+    let input = path.to_str().unwrap();
+    let uri = if is_uri(input) {
+        input.to_string()
+    } else {
+        // we need to pass absolute path to discoverer as file uri
+        format!("file://{}", path.canonicalize()?.to_str().unwrap())
+    };
+    Ok(discoverer.discover_uri(&uri)?)
 }
 
 pub fn discover(path: &Path) -> anyhow::Result<FileInfo> {
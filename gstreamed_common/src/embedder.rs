@@ -0,0 +1,15 @@
+//! Common interface for appearance embedding (ReID) models, used to re-identify tracked objects.
+
+use image::DynamicImage;
+
+use crate::bbox::Detections;
+
+/// An appearance embedding model, which maps each detected object into a feature vector,
+/// such that crops of the same object are close to each other.
+pub trait Embedder: Send + Sync {
+    /// Computes embeddings of `detections` cropped from the given `image`.
+    ///
+    /// Returns one L2 normalized embedding per bbox, in the order of `detections`.
+    fn embed(&self, image: &DynamicImage, detections: &Detections)
+        -> anyhow::Result<Vec<Vec<f32>>>;
+}
@@ -0,0 +1,136 @@
+//! Structured export of detections, so results can be consumed without decoding the output video.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::bbox::{Bbox, Detections};
+use crate::labels::LabelMap;
+
+/// Detections of a single frame, serialized as one JSON Lines record.
+#[derive(Serialize)]
+struct FrameRecord<'a> {
+    frame: u64,
+    /// Presentation timestamp of the frame in nanoseconds, if known.
+    pts_ns: Option<u64>,
+    width: u32,
+    height: u32,
+    detections: Vec<BboxRecord<'a>>,
+}
+
+/// A single bbox, in original frame pixel coordinates.
+#[derive(Serialize)]
+struct BboxRecord<'a> {
+    xmin: f32,
+    ymin: f32,
+    xmax: f32,
+    ymax: f32,
+    class_id: usize,
+    class: Cow<'a, str>,
+    confidence: f32,
+    tracker_id: Option<i64>,
+    /// Detected bbox, for tracks whose coordinates above are smoothed by the tracker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    observed: Option<[f32; 4]>,
+    /// Set for bboxes predicted by the tracker, on frames without detection.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    predicted: bool,
+}
+
+impl<'a> BboxRecord<'a> {
+    fn new(bbox: &Bbox, labels: &'a LabelMap) -> Self {
+        Self {
+            xmin: bbox.xmin,
+            ymin: bbox.ymin,
+            xmax: bbox.xmax,
+            ymax: bbox.ymax,
+            class_id: bbox.class,
+            class: labels.name(bbox.class),
+            confidence: bbox.detector_confidence,
+            tracker_id: bbox.tracker_id,
+            observed: bbox.observed,
+            predicted: bbox.predicted,
+        }
+    }
+}
+
+/// Writes per-frame detections as JSON Lines, one JSON object per frame.
+pub struct JsonLinesWriter<W: Write> {
+    writer: W,
+}
+
+impl JsonLinesWriter<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path` to write detections into.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .map_err(|err| anyhow::anyhow!("Failed to create {path:?}: {err}"))?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Writes `detections` of the frame with the given index as a single line.
+    pub fn write_frame(
+        &mut self,
+        frame: u64,
+        detections: &Detections,
+        labels: &LabelMap,
+    ) -> anyhow::Result<()> {
+        let record = FrameRecord {
+            frame,
+            pts_ns: detections.timestamp.map(|ts| ts.as_nanos() as u64),
+            width: detections.frame_dims.width as u32,
+            height: detections.frame_dims.height as u32,
+            detections: detections
+                .iter()
+                .map(|bbox| BboxRecord::new(bbox, labels))
+                .collect(),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn writes_one_line_per_frame() {
+    use crate::img_dimensions::ImgDimensions;
+    use std::time::Duration;
+
+    let mut detections = Detections::new(ImgDimensions::new(1280.0, 720.0));
+    detections.timestamp = Some(Duration::from_millis(40));
+    detections.push(Bbox {
+        detector_confidence: 0.5,
+        tracker_id: Some(3),
+        ..Bbox::test(10.0, 20.0, 110.0, 220.0, 0)
+    });
+
+    let mut writer = JsonLinesWriter::new(Vec::new());
+    let labels = LabelMap::coco();
+    writer.write_frame(1, &detections, &labels).unwrap();
+    writer
+        .write_frame(2, &Detections::new(detections.frame_dims), &labels)
+        .unwrap();
+
+    let output = String::from_utf8(writer.writer).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            r#"{"frame":1,"pts_ns":40000000,"width":1280,"height":720,"detections":[{"xmin":10.0,"ymin":20.0,"xmax":110.0,"ymax":220.0,"class_id":0,"class":"person","confidence":0.5,"tracker_id":3}]}"#,
+            r#"{"frame":2,"pts_ns":null,"width":1280,"height":720,"detections":[]}"#,
+        ]
+    );
+}
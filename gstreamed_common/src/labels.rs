@@ -0,0 +1,196 @@
+//! Class label names, so models trained on datasets other than COCO can be used.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::coco_classes;
+
+/// Class names, indexed by class id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelMap {
+    names: Vec<String>,
+}
+
+/// Class id key, which is an int in yaml, but has to be a string in json.
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+enum ClassId {
+    Int(usize),
+    Str(String),
+}
+
+/// Names as a list, or as a `class id -> name` mapping, e.g. in Ultralytics dataset yaml.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Names {
+    List(Vec<String>),
+    Map(BTreeMap<ClassId, String>),
+}
+
+/// Either an Ultralytics style dataset file with `names` field, or just the names.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LabelsFile {
+    Dataset { names: Names },
+    Names(Names),
+}
+
+impl LabelsFile {
+    fn into_names(self) -> Names {
+        match self {
+            LabelsFile::Dataset { names } | LabelsFile::Names(names) => names,
+        }
+    }
+}
+
+impl TryFrom<Names> for LabelMap {
+    type Error = anyhow::Error;
+
+    fn try_from(names: Names) -> Result<Self, Self::Error> {
+        let names = match names {
+            Names::List(names) => names,
+            Names::Map(map) => {
+                let map = map
+                    .into_iter()
+                    .map(|(id, name)| match id {
+                        ClassId::Int(id) => Ok((id, name)),
+                        ClassId::Str(id) => Ok((id.parse()?, name)),
+                    })
+                    .collect::<anyhow::Result<BTreeMap<usize, String>>>()?;
+                // Class ids must be contiguous, starting from 0.
+                for (expected, id) in map.keys().enumerate() {
+                    anyhow::ensure!(
+                        *id == expected,
+                        "Class ids must be contiguous starting from 0, class id {expected} is missing"
+                    );
+                }
+                map.into_values().collect()
+            }
+        };
+        anyhow::ensure!(!names.is_empty(), "Label map must not be empty");
+        Ok(Self { names })
+    }
+}
+
+impl LabelMap {
+    pub fn new(names: Vec<String>) -> Self {
+        Self { names }
+    }
+
+    /// 80 COCO classes.
+    pub fn coco() -> Self {
+        Self::new(coco_classes::NAMES.iter().map(|s| s.to_string()).collect())
+    }
+
+    /// Parses a text file with one class name per line, empty lines are ignored.
+    pub fn from_text(text: &str) -> anyhow::Result<Self> {
+        let names = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+        Names::List(names).try_into()
+    }
+
+    /// Parses yaml with a list of names or a `class id: name` mapping,
+    /// either at the top level, or under `names` key as in Ultralytics dataset yaml.
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        serde_yaml::from_str::<LabelsFile>(yaml)?
+            .into_names()
+            .try_into()
+    }
+
+    /// Parses json with a list of names or a `"class id": name` mapping,
+    /// either at the top level, or under `names` key.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        serde_json::from_str::<LabelsFile>(json)?
+            .into_names()
+            .try_into()
+    }
+
+    /// Parses `names` metadata embedded in Ultralytics onnx exports,
+    /// which is a python dict, such as `{0: 'person', 1: 'bicycle'}`.
+    pub fn from_ultralytics_metadata(names: &str) -> anyhow::Result<Self> {
+        // Python dict repr with int keys and quoted strings happens to be a valid yaml flow mapping.
+        serde_yaml::from_str::<Names>(names)?.try_into()
+    }
+
+    /// Loads label map from a `.txt`, `.yaml`/`.yml` or `.json` file.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let labels = match path.extension().and_then(|os_str| os_str.to_str()) {
+            Some("txt") => Self::from_text(&contents),
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => anyhow::bail!("Unsupported label file extension, expected .txt, .yaml or .json"),
+        };
+        labels.map_err(|err| err.context(format!("Failed to load labels from {path:?}")))
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn get(&self, class: usize) -> Option<&str> {
+        self.names.get(class).map(String::as_str)
+    }
+
+    /// Name of the `class`, or a placeholder with the class id for unknown classes.
+    pub fn name(&self, class: usize) -> Cow<'_, str> {
+        match self.get(class) {
+            Some(name) => Cow::Borrowed(name),
+            None => Cow::Owned(format!("class {class}")),
+        }
+    }
+
+    /// Looks up class id by its `name`.
+    pub fn class_id(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
+impl Default for LabelMap {
+    fn default() -> Self {
+        Self::coco()
+    }
+}
+
+#[test]
+fn labels_from_ultralytics_metadata() {
+    let labels =
+        LabelMap::from_ultralytics_metadata("{0: 'person', 1: 'traffic light', 2: \"dog's\"}")
+            .unwrap();
+    assert_eq!(labels.names(), ["person", "traffic light", "dog's"]);
+    assert_eq!(labels.name(1), "traffic light");
+    assert_eq!(labels.name(3), "class 3");
+}
+
+#[test]
+fn labels_from_dataset_files() {
+    let yaml = "path: ../datasets/x\nnames:\n  0: forklift\n  1: pallet\n";
+    let labels = LabelMap::from_yaml(yaml).unwrap();
+    assert_eq!(labels.names(), ["forklift", "pallet"]);
+
+    let labels = LabelMap::from_json(r#"{"0": "forklift", "1": "pallet"}"#).unwrap();
+    assert_eq!(labels.names(), ["forklift", "pallet"]);
+
+    let labels = LabelMap::from_json(r#"["forklift", "pallet"]"#).unwrap();
+    assert_eq!(labels.names(), ["forklift", "pallet"]);
+
+    let labels = LabelMap::from_text("forklift\n\npallet\n").unwrap();
+    assert_eq!(labels.names(), ["forklift", "pallet"]);
+
+    assert!(LabelMap::from_yaml("names:\n  0: forklift\n  2: pallet\n").is_err());
+}
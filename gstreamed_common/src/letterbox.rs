@@ -0,0 +1,260 @@
+//! Letterbox preprocessing, matching the behaviour of Ultralytics `LetterBox`:
+//! the image is scaled to fit the target dimensions keeping its aspect ratio,
+//! and the remainder is padded evenly on both sides with gray.
+//! With `scale_fill`, as used for RT-DETR, the image is stretched to the target dimensions instead.
+
+use fast_image_resize::{FilterType, ResizeAlg, ResizeOptions, Resizer};
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::bbox::{Bbox, Detections};
+use crate::img_dimensions::ImgDimensions;
+
+/// Value used for padding pixels in all channels.
+pub const PAD_VALUE: u8 = 114;
+
+/// Describes how an original image maps into its letterboxed version, and back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LetterboxTransform {
+    /// Dimensions of the original image.
+    pub og_dims: ImgDimensions,
+    /// Dimensions of the letterboxed image, including padding.
+    pub letterboxed_dims: ImgDimensions,
+    /// Scale ratios `[width, height]` from original to letterboxed image, equal unless scale filled.
+    pub ratio: [f32; 2],
+    /// Padding added to the left of the scaled image.
+    pub pad_left: u32,
+    /// Padding added to the top of the scaled image.
+    pub pad_top: u32,
+    /// Dimensions of the scaled image, without padding.
+    pub scaled_width: u32,
+    pub scaled_height: u32,
+}
+
+/// Python style rounding, which rounds half to even.
+fn py_round(value: f32) -> u32 {
+    value.round_ties_even() as u32
+}
+
+impl LetterboxTransform {
+    /// Computes the letterbox transform for an image of `og_dims` into `target_dims`.
+    ///
+    /// If `stride` is given, padding is minimized to the least amount necessary for
+    /// letterboxed dimensions to be divisible by `stride` (Ultralytics `auto` mode),
+    /// in which case letterboxed dimensions may be smaller than `target_dims`.
+    pub fn new(og_dims: ImgDimensions, target_dims: ImgDimensions, stride: Option<u32>) -> Self {
+        let ratio = (target_dims.height / og_dims.height).min(target_dims.width / og_dims.width);
+        let scaled_width = py_round(og_dims.width * ratio);
+        let scaled_height = py_round(og_dims.height * ratio);
+
+        let mut pad_w = target_dims.width - scaled_width as f32;
+        let mut pad_h = target_dims.height - scaled_height as f32;
+        if let Some(stride) = stride {
+            pad_w %= stride as f32;
+            pad_h %= stride as f32;
+        }
+        // Split padding evenly between both sides.
+        pad_w /= 2.0;
+        pad_h /= 2.0;
+        let pad_left = py_round(pad_w - 0.1);
+        let pad_right = py_round(pad_w + 0.1);
+        let pad_top = py_round(pad_h - 0.1);
+        let pad_bottom = py_round(pad_h + 0.1);
+
+        Self {
+            og_dims,
+            letterboxed_dims: ImgDimensions::new(
+                (pad_left + scaled_width + pad_right) as f32,
+                (pad_top + scaled_height + pad_bottom) as f32,
+            ),
+            ratio: [ratio, ratio],
+            pad_left,
+            pad_top,
+            scaled_width,
+            scaled_height,
+        }
+    }
+
+    /// Computes the transform for an image of `og_dims` stretched into `target_dims`, without padding
+    /// (Ultralytics `scale_fill` mode).
+    pub fn scale_fill(og_dims: ImgDimensions, target_dims: ImgDimensions) -> Self {
+        Self {
+            og_dims,
+            letterboxed_dims: target_dims,
+            ratio: [
+                target_dims.width / og_dims.width,
+                target_dims.height / og_dims.height,
+            ],
+            pad_left: 0,
+            pad_top: 0,
+            scaled_width: target_dims.width as u32,
+            scaled_height: target_dims.height as u32,
+        }
+    }
+
+    /// Maps `bbox` from letterboxed image coordinates back to the original image coordinates,
+    /// clamping it to the original image.
+    pub fn bbox_to_original(&self, bbox: &mut Bbox) {
+        let [ratio_x, ratio_y] = self.ratio;
+        let x = |x: f32| ((x - self.pad_left as f32) / ratio_x).clamp(0.0, self.og_dims.width);
+        let y = |y: f32| ((y - self.pad_top as f32) / ratio_y).clamp(0.0, self.og_dims.height);
+        bbox.xmin = x(bbox.xmin);
+        bbox.xmax = x(bbox.xmax);
+        bbox.ymin = y(bbox.ymin);
+        bbox.ymax = y(bbox.ymax);
+        for kp in bbox.data.iter_mut() {
+            kp.x = x(kp.x);
+            kp.y = y(kp.y);
+        }
+    }
+
+    /// Maps `detections` from letterboxed image coordinates back to the original image coordinates.
+    pub fn to_original(&self, detections: &mut Detections) {
+        for bbox in detections.iter_mut() {
+            self.bbox_to_original(bbox);
+        }
+        detections.frame_dims = self.og_dims;
+    }
+}
+
+/// Letterboxes the `image` into `target_dims`, see [LetterboxTransform::new] for `stride`.
+///
+/// Returns the letterboxed image and the transform to map coordinates back to the original `image`.
+pub fn letterbox(
+    image: &DynamicImage,
+    target_dims: ImgDimensions,
+    stride: Option<u32>,
+) -> anyhow::Result<(RgbImage, LetterboxTransform)> {
+    let og_dims: ImgDimensions = (image.width(), image.height()).into();
+    let transform = LetterboxTransform::new(og_dims, target_dims, stride);
+    log::debug!("letterbox: {transform:?}");
+    let scaled_image = resize(image, &transform)?;
+
+    // Paste the scaled image into the middle of padded canvas.
+    let mut letterboxed = RgbImage::from_pixel(
+        transform.letterboxed_dims.width as u32,
+        transform.letterboxed_dims.height as u32,
+        Rgb([PAD_VALUE; 3]),
+    );
+    image::imageops::replace(
+        &mut letterboxed,
+        &scaled_image,
+        transform.pad_left as i64,
+        transform.pad_top as i64,
+    );
+
+    Ok((letterboxed, transform))
+}
+
+/// Stretches the `image` into `target_dims`, see [LetterboxTransform::scale_fill].
+///
+/// Returns the stretched image and the transform to map coordinates back to the original `image`.
+pub fn scale_fill(
+    image: &DynamicImage,
+    target_dims: ImgDimensions,
+) -> anyhow::Result<(RgbImage, LetterboxTransform)> {
+    let og_dims: ImgDimensions = (image.width(), image.height()).into();
+    let transform = LetterboxTransform::scale_fill(og_dims, target_dims);
+    log::debug!("scale_fill: {transform:?}");
+    Ok((resize(image, &transform)?, transform))
+}
+
+/// Resizes the `image` to the scaled dimensions of the `transform`, without padding.
+fn resize(image: &DynamicImage, transform: &LetterboxTransform) -> anyhow::Result<RgbImage> {
+    // Use `fast_image_resize` crate to resize the image.
+    // It has unsafe, but it is way faster than plain `image`, unfortunately...
+    let mut scaled_image = fast_image_resize::images::Image::new(
+        transform.scaled_width,
+        transform.scaled_height,
+        fast_image_resize::PixelType::U8x3,
+    );
+    let image = DynamicImage::ImageRgb8(image.to_rgb8());
+    Resizer::new().resize(
+        &image,
+        &mut scaled_image,
+        &ResizeOptions::new().resize_alg(ResizeAlg::Convolution(FilterType::Bilinear)),
+    )?;
+    Ok(RgbImage::from_raw(
+        transform.scaled_width,
+        transform.scaled_height,
+        scaled_image.into_vec(),
+    )
+    .unwrap())
+}
+
+#[test]
+fn letterbox_pads_evenly() {
+    let transform = LetterboxTransform::new(
+        ImgDimensions::new(1280.0, 720.0),
+        ImgDimensions::new(640.0, 640.0),
+        None,
+    );
+    assert_eq!(transform.ratio, [0.5, 0.5]);
+    assert_eq!(
+        (transform.scaled_width, transform.scaled_height),
+        (640, 360)
+    );
+    assert_eq!((transform.pad_left, transform.pad_top), (0, 140));
+    assert_eq!(transform.letterboxed_dims, ImgDimensions::new(640.0, 640.0));
+}
+
+#[test]
+fn letterbox_auto_stride_minimizes_padding() {
+    let transform = LetterboxTransform::new(
+        ImgDimensions::new(1280.0, 720.0),
+        ImgDimensions::new(640.0, 640.0),
+        Some(32),
+    );
+    assert_eq!((transform.pad_left, transform.pad_top), (0, 12));
+    assert_eq!(transform.letterboxed_dims, ImgDimensions::new(640.0, 384.0));
+
+    let transform = LetterboxTransform::new(
+        ImgDimensions::new(500.0, 375.0),
+        ImgDimensions::new(640.0, 640.0),
+        Some(32),
+    );
+    assert_eq!(
+        (transform.scaled_width, transform.scaled_height),
+        (640, 480)
+    );
+    assert_eq!(transform.letterboxed_dims, ImgDimensions::new(640.0, 480.0));
+
+    // Odd padding is split with the extra pixel at the bottom/right.
+    let transform = LetterboxTransform::new(
+        ImgDimensions::new(499.0, 375.0),
+        ImgDimensions::new(640.0, 640.0),
+        Some(32),
+    );
+    assert_eq!(
+        (transform.scaled_width, transform.scaled_height),
+        (640, 481)
+    );
+    assert_eq!(transform.pad_top, 15);
+    assert_eq!(transform.letterboxed_dims, ImgDimensions::new(640.0, 512.0));
+}
+
+#[test]
+fn letterbox_inverse_maps_to_original() {
+    let transform = LetterboxTransform::new(
+        ImgDimensions::new(1280.0, 720.0),
+        ImgDimensions::new(640.0, 640.0),
+        None,
+    );
+    let mut bbox = Bbox::test(10.0, 150.0, 650.0, 300.0, 0);
+    let mut restored = bbox.clone();
+    transform.bbox_to_original(&mut restored);
+    assert_eq!(
+        (restored.xmin, restored.ymin, restored.xmax, restored.ymax),
+        (20.0, 20.0, 1280.0, 320.0)
+    );
+
+    // Scale filled image is stretched by 2 horizontally and by 1.125 vertically.
+    let transform = LetterboxTransform::scale_fill(
+        ImgDimensions::new(1280.0, 720.0),
+        ImgDimensions::new(640.0, 640.0),
+    );
+    transform.bbox_to_original(&mut bbox);
+    assert_eq!(
+        (bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax),
+        (20.0, 168.75, 1280.0, 337.5)
+    );
+}
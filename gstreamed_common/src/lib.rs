@@ -1,7 +1,16 @@
 pub mod annotate;
+pub mod assignment;
 pub mod bbox;
 pub mod coco_classes;
+pub mod coco_eval;
+pub mod detector;
 pub mod discovery;
+pub mod embedder;
+pub mod export;
 pub mod frame_times;
 pub mod img_dimensions;
+pub mod labels;
+pub mod letterbox;
+pub mod mot;
+pub mod mot_eval;
 pub mod pipeline;
@@ -0,0 +1,235 @@
+//! MOTChallenge `det.txt` / `gt.txt` style files, as used by the standard MOT evaluation toolkits.
+//!
+//! Each row is `frame, id, left, top, width, height, conf, class, visibility`,
+//! with 1-based frame numbers and `-1` id for untracked detections.
+//! Frame indices in our api are 0-based, as everywhere else, and are converted on read/write.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::bbox::{Bbox, Detections};
+use crate::img_dimensions::ImgDimensions;
+
+/// A single MOTChallenge row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotRow {
+    /// 0-based frame index.
+    pub frame: u64,
+    /// Track id, `-1` for untracked detections.
+    pub id: i64,
+    pub left: f32,
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+    pub conf: f32,
+    /// Class id, `-1` if unknown.
+    pub class: i64,
+    /// Visibility ratio, `-1` if unknown.
+    pub visibility: f32,
+}
+
+impl MotRow {
+    pub fn from_bbox(frame: u64, bbox: &Bbox) -> Self {
+        Self {
+            frame,
+            id: bbox.tracker_id.unwrap_or(-1),
+            left: bbox.xmin,
+            top: bbox.ymin,
+            width: bbox.xmax - bbox.xmin,
+            height: bbox.ymax - bbox.ymin,
+            conf: bbox.detector_confidence,
+            class: bbox.class as i64,
+            // We don't estimate occlusion, so everything we see is fully visible.
+            visibility: 1.0,
+        }
+    }
+
+    /// Converts the row into a [Bbox], unknown classes are mapped to class 0.
+    pub fn to_bbox(&self) -> Bbox {
+        Bbox {
+            xmin: self.left,
+            ymin: self.top,
+            xmax: self.left + self.width,
+            ymax: self.top + self.height,
+            detector_confidence: self.conf,
+            tracker_confidence: 0.0,
+            data: vec![],
+            class: self.class.max(0) as usize,
+            tracker_id: (self.id >= 0).then_some(self.id),
+            observed: None,
+            predicted: false,
+        }
+    }
+
+    /// Parses a comma separated row. Rows may have 7 to 10 columns, as different
+    /// MOTChallenge benchmarks have them, missing class and visibility are `-1`.
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let cols = line
+            .split(',')
+            .map(|col| col.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| anyhow::anyhow!("Invalid MOT row {line:?}: {err}"))?;
+        anyhow::ensure!(
+            (7..=10).contains(&cols.len()),
+            "MOT row must have 7 to 10 columns, got {}: {line:?}",
+            cols.len()
+        );
+        anyhow::ensure!(cols[0] >= 1.0, "MOT frame numbers start from 1: {line:?}");
+        Ok(Self {
+            frame: cols[0] as u64 - 1,
+            id: cols[1] as i64,
+            left: cols[2],
+            top: cols[3],
+            width: cols[4],
+            height: cols[5],
+            conf: cols[6],
+            class: cols.get(7).map(|&class| class as i64).unwrap_or(-1),
+            visibility: cols.get(8).copied().unwrap_or(-1.0),
+        })
+    }
+}
+
+/// Writes detections or tracks as MOTChallenge rows, one per bbox.
+pub struct MotWriter<W: Write> {
+    writer: W,
+}
+
+impl MotWriter<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path` to write rows into.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .map_err(|err| anyhow::anyhow!("Failed to create {path:?}: {err}"))?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> MotWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write_row(&mut self, row: &MotRow) -> anyhow::Result<()> {
+        writeln!(
+            self.writer,
+            "{},{},{:.2},{:.2},{:.2},{:.2},{:.4},{},{}",
+            row.frame + 1,
+            row.id,
+            row.left,
+            row.top,
+            row.width,
+            row.height,
+            row.conf,
+            row.class,
+            row.visibility
+        )?;
+        Ok(())
+    }
+
+    /// Writes all bboxes of `detections` on the frame with 0-based index `frame`.
+    pub fn write_frame(&mut self, frame: u64, detections: &Detections) -> anyhow::Result<()> {
+        for bbox in detections {
+            self.write_row(&MotRow::from_bbox(frame, bbox))?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads all rows from MOTChallenge `reader`, skipping empty lines.
+pub fn read_rows(reader: impl BufRead) -> anyhow::Result<Vec<MotRow>> {
+    let mut rows = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(MotRow::parse(&line)?);
+    }
+    Ok(rows)
+}
+
+/// Reads all rows from MOTChallenge file at `path`.
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<MotRow>> {
+    let file = File::open(path).map_err(|err| anyhow::anyhow!("Failed to open {path:?}: {err}"))?;
+    read_rows(BufReader::new(file))
+        .map_err(|err| err.context(format!("Failed to read MOT rows from {path:?}")))
+}
+
+/// Groups `rows` into per frame [Detections], keyed by 0-based frame index.
+///
+/// Frames without any rows are left out, so sparse or huge frame numbers take no extra space.
+pub fn rows_to_detections(rows: &[MotRow], frame_dims: ImgDimensions) -> BTreeMap<u64, Detections> {
+    let mut frames = BTreeMap::new();
+    for row in rows {
+        frames
+            .entry(row.frame)
+            .or_insert_with(|| Detections::new(frame_dims))
+            .push(row.to_bbox());
+    }
+    frames
+}
+
+/// Reads MOTChallenge file at `path` into per frame [Detections], see [rows_to_detections].
+///
+/// MOT files don't record frame dimensions, so they have to be given as `frame_dims`.
+pub fn read_detections(
+    path: &Path,
+    frame_dims: ImgDimensions,
+) -> anyhow::Result<BTreeMap<u64, Detections>> {
+    Ok(rows_to_detections(&read_file(path)?, frame_dims))
+}
+
+/// Flattens per frame [Detections], keyed by 0-based frame index, into rows.
+pub fn detections_to_rows(frames: &BTreeMap<u64, Detections>) -> Vec<MotRow> {
+    frames
+        .iter()
+        .flat_map(|(&frame, detections)| {
+            detections
+                .iter()
+                .map(move |bbox| MotRow::from_bbox(frame, bbox))
+        })
+        .collect()
+}
+
+#[test]
+fn mot_rows_roundtrip() {
+    let bbox = Bbox {
+        detector_confidence: 0.75,
+        tracker_id: Some(7),
+        ..Bbox::test(10.0, 20.0, 110.5, 220.0, 2)
+    };
+    let mut detections = Detections::new(ImgDimensions::new(1280.0, 720.0));
+    detections.push(bbox.clone());
+
+    let mut writer = MotWriter::new(Vec::new());
+    writer.write_frame(2, &detections).unwrap();
+    let output = String::from_utf8(writer.writer).unwrap();
+    assert_eq!(output, "3,7,10.00,20.00,100.50,200.00,0.7500,2,1\n");
+
+    // MOT16 style det.txt row, with unknown id, class and 3d coords.
+    let input = format!("{output}\n1,-1,1,2,3,4,0.5,-1,-1,-1\n");
+    let rows = read_rows(input.as_bytes()).unwrap();
+    assert_eq!(rows[0], MotRow::from_bbox(2, &bbox));
+    assert_eq!(rows[1].id, -1);
+    assert_eq!(rows[1].to_bbox().tracker_id, None);
+
+    let frames = rows_to_detections(&rows, detections.frame_dims);
+    assert_eq!(frames.keys().collect::<Vec<_>>(), [&0, &2]);
+    assert_eq!(frames[&0].len(), 1);
+    let frame_numbers: Vec<_> = detections_to_rows(&frames)
+        .iter()
+        .map(|row| row.frame)
+        .collect();
+    assert_eq!(frame_numbers, [0, 2]);
+
+    // Huge frame numbers don't allocate all the frames before them.
+    let huge = [MotRow::from_bbox(u64::MAX - 1, &bbox)];
+    assert_eq!(rows_to_detections(&huge, detections.frame_dims).len(), 1);
+    assert_eq!(frames[&2].as_slice(), [bbox]);
+}
@@ -0,0 +1,364 @@
+//! MOTChallenge tracking metrics: CLEAR MOT (MOTA, MOTP, ID switches), IDF1 and HOTA,
+//! following the TrackEval implementation.
+//!
+//! Evaluation is class agnostic. Ground truth rows with zero `conf` are marked as ignored
+//! by MOTChallenge and are skipped, but distractor preprocessing of the official toolkit
+//! is not done, so results may slightly differ from the official ones.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::assignment::linear_assignment;
+use crate::bbox::{iou, Bbox};
+use crate::mot::MotRow;
+
+/// IoU threshold for a match in CLEAR MOT and identity metrics.
+pub const MATCH_IOU_THRESHOLD: f32 = 0.5;
+
+/// Number of localization thresholds 0.05:0.05:0.95 HOTA is averaged over.
+const HOTA_ALPHAS: usize = 19;
+
+const EPS: f32 = 1e-6;
+
+/// Tracking metrics, see [evaluate].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MotMetrics {
+    pub mota: f32,
+    /// Average IoU of matched bboxes.
+    pub motp: f32,
+    pub id_switches: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    pub idf1: f32,
+    pub idp: f32,
+    pub idr: f32,
+    pub hota: f32,
+    /// HOTA detection accuracy.
+    pub deta: f32,
+    /// HOTA association accuracy.
+    pub assa: f32,
+    pub num_gt_dets: usize,
+    pub num_tracker_dets: usize,
+    pub num_gt_ids: usize,
+    pub num_tracker_ids: usize,
+}
+
+/// Ground truth and tracker bboxes on a single frame, with dense ids.
+#[derive(Default)]
+struct Frame {
+    gt_ids: Vec<usize>,
+    gt_bboxes: Vec<Bbox>,
+    tracker_ids: Vec<usize>,
+    tracker_bboxes: Vec<Bbox>,
+}
+
+impl Frame {
+    /// IoU between each ground truth and tracker bbox, `[gt][tracker]`.
+    fn similarity(&self) -> Vec<Vec<f32>> {
+        self.gt_bboxes
+            .iter()
+            .map(|gt| {
+                self.tracker_bboxes
+                    .iter()
+                    .map(|tracked| iou(gt, tracked))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Maps arbitrary ids of `rows` to dense 0-based ones, returns the dense ids and their count.
+fn dense_ids<'a>(rows: impl Iterator<Item = &'a MotRow>) -> (Vec<usize>, usize) {
+    let mut ids = HashMap::new();
+    let dense = rows
+        .map(|row| {
+            let next = ids.len();
+            *ids.entry(row.id).or_insert(next)
+        })
+        .collect();
+    (dense, ids.len())
+}
+
+/// Assignment maximizing the total `score`, keeping only pairs with positive score.
+fn max_score_matches(score: &[Vec<f32>]) -> Vec<(usize, usize)> {
+    let cost: Vec<Vec<f64>> = score
+        .iter()
+        .map(|row| row.iter().map(|&s| -s as f64).collect())
+        .collect();
+    linear_assignment(&cost)
+        .into_iter()
+        .filter(|&(row, col)| score[row][col] > EPS)
+        .collect()
+}
+
+fn ratio(num: f32, denom: f32) -> f32 {
+    num / denom.max(1.0)
+}
+
+/// Evaluates `tracks` against `ground_truth`, both as MOTChallenge rows.
+pub fn evaluate(ground_truth: &[MotRow], tracks: &[MotRow]) -> MotMetrics {
+    let ground_truth: Vec<_> = ground_truth.iter().filter(|row| row.conf != 0.0).collect();
+    let (gt_ids, num_gt_ids) = dense_ids(ground_truth.iter().copied());
+    let (tracker_ids, num_tracker_ids) = dense_ids(tracks.iter());
+
+    // Frames without any rows don't affect the metrics, so only the ones with rows are kept, in order.
+    let mut frames: BTreeMap<u64, Frame> = BTreeMap::new();
+    for (row, id) in ground_truth.iter().zip(gt_ids) {
+        let frame = frames.entry(row.frame).or_default();
+        frame.gt_ids.push(id);
+        frame.gt_bboxes.push(row.to_bbox());
+    }
+    for (row, id) in tracks.iter().zip(tracker_ids) {
+        let frame = frames.entry(row.frame).or_default();
+        frame.tracker_ids.push(id);
+        frame.tracker_bboxes.push(row.to_bbox());
+    }
+    let frames: Vec<Frame> = frames.into_values().collect();
+    let similarities: Vec<_> = frames.iter().map(Frame::similarity).collect();
+
+    let mut metrics = MotMetrics {
+        num_gt_dets: ground_truth.len(),
+        num_tracker_dets: tracks.len(),
+        num_gt_ids,
+        num_tracker_ids,
+        ..Default::default()
+    };
+    clear_metrics(&frames, &similarities, &mut metrics);
+    identity_metrics(&frames, &similarities, &mut metrics);
+    hota_metrics(&frames, &similarities, &mut metrics);
+    metrics
+}
+
+/// MOTA, MOTP and ID switches.
+fn clear_metrics(frames: &[Frame], similarities: &[Vec<Vec<f32>>], metrics: &mut MotMetrics) {
+    // Tracker matched to each gt id on the previous frame, and on the last frame it was matched.
+    let mut prev_tracker = vec![None; metrics.num_gt_ids];
+    let mut last_tracker = vec![None; metrics.num_gt_ids];
+    let mut iou_sum = 0.0;
+    for (frame, similarity) in frames.iter().zip(similarities) {
+        if frame.gt_ids.is_empty() || frame.tracker_ids.is_empty() {
+            metrics.false_positives += frame.tracker_ids.len();
+            metrics.false_negatives += frame.gt_ids.len();
+            continue;
+        }
+
+        // Prefer continuing matches from the previous frame.
+        let score: Vec<Vec<f32>> = similarity
+            .iter()
+            .zip(&frame.gt_ids)
+            .map(|(row, &gt_id)| {
+                row.iter()
+                    .zip(&frame.tracker_ids)
+                    .map(|(&sim, &tracker_id)| {
+                        if sim < MATCH_IOU_THRESHOLD - EPS {
+                            0.0
+                        } else if prev_tracker[gt_id] == Some(tracker_id) {
+                            1000.0 + sim
+                        } else {
+                            sim
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let matches = max_score_matches(&score);
+
+        prev_tracker.fill(None);
+        for &(row, col) in &matches {
+            let gt_id = frame.gt_ids[row];
+            let tracker_id = frame.tracker_ids[col];
+            if last_tracker[gt_id].is_some_and(|last| last != tracker_id) {
+                metrics.id_switches += 1;
+            }
+            prev_tracker[gt_id] = Some(tracker_id);
+            last_tracker[gt_id] = Some(tracker_id);
+            iou_sum += similarity[row][col];
+        }
+        metrics.true_positives += matches.len();
+        metrics.false_positives += frame.tracker_ids.len() - matches.len();
+        metrics.false_negatives += frame.gt_ids.len() - matches.len();
+    }
+
+    let errors = metrics.false_negatives + metrics.false_positives + metrics.id_switches;
+    metrics.mota = 1.0 - ratio(errors as f32, metrics.num_gt_dets as f32);
+    metrics.motp = ratio(iou_sum, metrics.true_positives as f32);
+}
+
+/// IDF1, IDP and IDR.
+fn identity_metrics(frames: &[Frame], similarities: &[Vec<Vec<f32>>], metrics: &mut MotMetrics) {
+    // Number of frames each gt id could be matched with each tracker id.
+    let mut match_counts = vec![vec![0.0; metrics.num_tracker_ids]; metrics.num_gt_ids];
+    for (frame, similarity) in frames.iter().zip(similarities) {
+        for (row, &gt_id) in frame.gt_ids.iter().enumerate() {
+            for (col, &tracker_id) in frame.tracker_ids.iter().enumerate() {
+                if similarity[row][col] >= MATCH_IOU_THRESHOLD - EPS {
+                    match_counts[gt_id][tracker_id] += 1.0;
+                }
+            }
+        }
+    }
+
+    // Globally optimal one to one identity matching.
+    let id_tp: f32 = max_score_matches(&match_counts)
+        .into_iter()
+        .map(|(gt_id, tracker_id)| match_counts[gt_id][tracker_id])
+        .sum();
+    let id_fn = metrics.num_gt_dets as f32 - id_tp;
+    let id_fp = metrics.num_tracker_dets as f32 - id_tp;
+    metrics.idr = ratio(id_tp, id_tp + id_fn);
+    metrics.idp = ratio(id_tp, id_tp + id_fp);
+    metrics.idf1 = ratio(id_tp, id_tp + 0.5 * id_fp + 0.5 * id_fn);
+}
+
+/// HOTA, DetA and AssA, averaged over localization thresholds.
+fn hota_metrics(frames: &[Frame], similarities: &[Vec<Vec<f32>>], metrics: &mut MotMetrics) {
+    let (num_gt_ids, num_tracker_ids) = (metrics.num_gt_ids, metrics.num_tracker_ids);
+    let mut gt_id_counts = vec![0.0; num_gt_ids];
+    let mut tracker_id_counts = vec![0.0; num_tracker_ids];
+
+    // Soft count of how well each gt id aligns with each tracker id over the whole sequence.
+    let mut potential_matches = vec![vec![0.0; num_tracker_ids]; num_gt_ids];
+    for (frame, similarity) in frames.iter().zip(similarities) {
+        let gt_sums: Vec<f32> = similarity.iter().map(|row| row.iter().sum()).collect();
+        for (col, &tracker_id) in frame.tracker_ids.iter().enumerate() {
+            let tracker_sum: f32 = similarity.iter().map(|row| row[col]).sum();
+            for (row, &gt_id) in frame.gt_ids.iter().enumerate() {
+                let sim = similarity[row][col];
+                let denom = gt_sums[row] + tracker_sum - sim;
+                if denom > EPS {
+                    potential_matches[gt_id][tracker_id] += sim / denom;
+                }
+            }
+            tracker_id_counts[tracker_id] += 1.0;
+        }
+        for &gt_id in &frame.gt_ids {
+            gt_id_counts[gt_id] += 1.0;
+        }
+    }
+    let global_alignment: Vec<Vec<f32>> = potential_matches
+        .iter()
+        .zip(&gt_id_counts)
+        .map(|(row, gt_count)| {
+            row.iter()
+                .zip(&tracker_id_counts)
+                .map(|(potential, tracker_count)| {
+                    potential / (gt_count + tracker_count - potential)
+                })
+                .collect()
+        })
+        .collect();
+
+    let alphas: Vec<f32> = (1..=HOTA_ALPHAS).map(|a| a as f32 * 0.05).collect();
+    let mut tp = [0.0; HOTA_ALPHAS];
+    let mut fn_ = [0.0; HOTA_ALPHAS];
+    let mut fp = [0.0; HOTA_ALPHAS];
+    let mut match_counts = vec![vec![vec![0.0; num_tracker_ids]; num_gt_ids]; HOTA_ALPHAS];
+    for (frame, similarity) in frames.iter().zip(similarities) {
+        let (num_gt, num_tracker) = (frame.gt_ids.len() as f32, frame.tracker_ids.len() as f32);
+        if frame.gt_ids.is_empty() || frame.tracker_ids.is_empty() {
+            fp.iter_mut().for_each(|fp| *fp += num_tracker);
+            fn_.iter_mut().for_each(|fn_| *fn_ += num_gt);
+            continue;
+        }
+
+        let score: Vec<Vec<f32>> = similarity
+            .iter()
+            .zip(&frame.gt_ids)
+            .map(|(row, &gt_id)| {
+                row.iter()
+                    .zip(&frame.tracker_ids)
+                    .map(|(sim, &tracker_id)| global_alignment[gt_id][tracker_id] * sim)
+                    .collect()
+            })
+            .collect();
+        let matches = max_score_matches(&score);
+
+        for (a, &alpha) in alphas.iter().enumerate() {
+            let mut num_matches = 0.0;
+            for &(row, col) in &matches {
+                if similarity[row][col] >= alpha - EPS {
+                    num_matches += 1.0;
+                    match_counts[a][frame.gt_ids[row]][frame.tracker_ids[col]] += 1.0;
+                }
+            }
+            tp[a] += num_matches;
+            fn_[a] += num_gt - num_matches;
+            fp[a] += num_tracker - num_matches;
+        }
+    }
+
+    let mut hota = 0.0;
+    let mut deta = 0.0;
+    let mut assa = 0.0;
+    for a in 0..HOTA_ALPHAS {
+        let mut ass_sum = 0.0;
+        for (gt_id, row) in match_counts[a].iter().enumerate() {
+            for (tracker_id, &count) in row.iter().enumerate() {
+                if count > 0.0 {
+                    let denom = gt_id_counts[gt_id] + tracker_id_counts[tracker_id] - count;
+                    ass_sum += count * ratio(count, denom);
+                }
+            }
+        }
+        let ass_a = ratio(ass_sum, tp[a]);
+        let det_a = ratio(tp[a], tp[a] + fn_[a] + fp[a]);
+        hota += (det_a * ass_a).sqrt();
+        deta += det_a;
+        assa += ass_a;
+    }
+    metrics.hota = hota / HOTA_ALPHAS as f32;
+    metrics.deta = deta / HOTA_ALPHAS as f32;
+    metrics.assa = assa / HOTA_ALPHAS as f32;
+}
+
+#[cfg(test)]
+fn test_row(frame: u64, id: i64, left: f32) -> MotRow {
+    MotRow {
+        frame,
+        id,
+        left,
+        top: 0.0,
+        width: 99.0,
+        height: 99.0,
+        conf: 1.0,
+        class: -1,
+        visibility: 1.0,
+    }
+}
+
+#[test]
+fn perfect_tracks_score_one() {
+    // Frame numbers may be sparse and huge.
+    let gt: Vec<_> = [0, 1, 2, 1000, u64::MAX - 1]
+        .into_iter()
+        .flat_map(|frame| [test_row(frame, 1, 0.0), test_row(frame, 2, 200.0)])
+        .collect();
+    let metrics = evaluate(&gt, &gt);
+    assert_eq!(metrics.mota, 1.0);
+    assert_eq!(metrics.motp, 1.0);
+    assert_eq!(metrics.idf1, 1.0);
+    assert!((metrics.hota - 1.0).abs() < 1e-6);
+    assert_eq!(metrics.id_switches, 0);
+}
+
+#[test]
+fn id_switch_is_counted() {
+    let gt: Vec<_> = (0..4).map(|frame| test_row(frame, 1, 0.0)).collect();
+    // Tracker changes id halfway, and has a false positive on the last frame.
+    let mut tracks: Vec<_> = (0..4)
+        .map(|frame| test_row(frame, if frame < 2 { 7 } else { 8 }, 0.0))
+        .collect();
+    tracks.push(test_row(3, 9, 300.0));
+
+    let metrics = evaluate(&gt, &tracks);
+    assert_eq!(metrics.id_switches, 1);
+    assert_eq!(metrics.true_positives, 4);
+    assert_eq!(metrics.false_positives, 1);
+    assert_eq!(metrics.false_negatives, 0);
+    assert_eq!(metrics.mota, 1.0 - 2.0 / 4.0);
+    // Best identity match covers 2 out of 4 gt and 5 tracker bboxes.
+    assert!((metrics.idf1 - 2.0 / 4.5).abs() < 1e-6);
+    // Half of the gt frames are associated with each tracker id.
+    assert!((metrics.deta - 0.8).abs() < 1e-6);
+    assert!((metrics.assa - 0.5).abs() < 1e-6);
+}
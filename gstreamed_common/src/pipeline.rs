@@ -1,25 +1,70 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
 use gstreamer::prelude::*;
-use gstreamer::{self as gst, Buffer};
-use gstreamer::{glib, PadProbeData, PadProbeReturn, PadProbeType};
+use gstreamer::{self as gst, glib};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
 
-fn file_src_bin(input_file: &str) -> Result<gst::Element, glib::BoolError> {
-    let bin = gst::Bin::new();
-    // filesrc -> decodebin -> queue
-    // filesrc, well, it reads a file
-    let source = gst::ElementFactory::make_with_name("filesrc", None)?;
-    source.set_property_from_str("location", input_file);
+/// Decoded frames queued for processing, when decoding a file.
+const MAX_QUEUED_FRAMES: usize = 2;
+
+/// Whether `input` is an URI, like `rtsp://camera/stream`, rather than a file path.
+pub fn is_uri(input: &str) -> bool {
+    input.contains("://")
+}
+
+/// Path of the annotated output for the given `input`.
+///
+/// For files, `.out.mkv` is appended to the file name,
+/// for URIs, the output is named after the last URI path segment, in the current directory.
+pub fn output_path(input: &str) -> String {
+    if !is_uri(input) {
+        return format!("{input}.out.mkv");
+    }
+    let (_scheme, rest) = input.split_once("://").unwrap();
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let name: String = rest
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("stream")
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{name}.out.mkv")
+}
 
+fn src_bin(input: &str) -> Result<gst::Element, glib::BoolError> {
+    let bin = gst::Bin::new();
+    // filesrc -> decodebin -> queue, or uridecodebin -> queue
     // decodebin automagically determines the input format
     // and constructs and links the appropriate decoder
-    let decode_bin = gst::ElementFactory::make_with_name("decodebin", None)?;
+    let decode_bin = if is_uri(input) {
+        // uridecodebin also picks the source element for the URI scheme, including live ones like rtsp
+        let decode_bin = gst::ElementFactory::make_with_name("uridecodebin", None)?;
+        decode_bin.set_property_from_str("uri", input);
+        bin.add(&decode_bin)?;
+        decode_bin
+    } else {
+        // filesrc, well, it reads a file
+        let source = gst::ElementFactory::make_with_name("filesrc", None)?;
+        source.set_property_from_str("location", input);
+        let decode_bin = gst::ElementFactory::make_with_name("decodebin", None)?;
+        bin.add_many([&source, &decode_bin])?;
+        // eager link filesrc -> decodebin
+        gst::Element::link_many([&source, &decode_bin])?;
+        decode_bin
+    };
 
     // finally, we use a queue so we have a late linking target
     // because decodebin's automagic needs to read the file and so is constructed "late"
     let queue = gst::ElementFactory::make_with_name("queue", None)?;
-
-    bin.add_many([&source, &decode_bin, &queue])?;
-    // eager link filesrc -> decodebin
-    gst::Element::link_many([&source, &decode_bin])?;
+    bin.add(&queue)?;
 
     // construct ghost src pad for the bin we cooking here
     let queue_src = queue.static_pad("src").unwrap();
@@ -39,32 +84,162 @@ fn file_src_bin(input_file: &str) -> Result<gst::Element, glib::BoolError> {
             pad.link(&sink_pad)
                 .expect("Could not link decodebin src pad to queue sink pad");
         } else {
-            eprintln!("Late linking: file_src_bin queue element has been dropped");
+            eprintln!("Late linking: src_bin queue element has been dropped");
         }
     });
 
     Ok(bin.upcast())
 }
 
-// filesrc -> decodebin -> [candle] -> queue -> encode -> mkvmux
-/// Builds gst pipeline that takes input video, decodes it, runs inference
-/// on the decoded frames, and then annotates the frame with inference output.
+/// Gst pipeline built by [build_pipeline], decoded frames are processed outside of it.
+///
+/// Decoding runs up to an `appsink`, which hands frames over to the [Receiver] returned by [InferencePipeline::play].
+/// Processed frames are pushed back into an `appsrc`, which feeds encoding.
+/// This way decoding, processing and encoding run in parallel, on different threads.
+pub struct InferencePipeline {
+    pub pipeline: gst::Pipeline,
+    appsink: AppSink,
+    appsrc: AppSrc,
+    /// Sender of decoded frames, dropped at the end of stream, which ends the [Receiver].
+    frames: Arc<Mutex<Option<SyncSender<gst::Buffer>>>>,
+}
+
+impl InferencePipeline {
+    /// Starts the pipeline, returning the receiver of decoded frames, in order.
+    ///
+    /// When decoding a file, decoding waits for queued frames to be processed.
+    /// Live sources, like cameras, can't wait, so if the previous frame hasn't been picked up yet,
+    /// new frames are dropped instead of queued, so the processed frames don't fall behind.
+    pub fn play(&self) -> Result<Receiver<gst::Buffer>, gst::StateChangeError> {
+        // Live sources don't produce data when paused, so they can't preroll.
+        let live =
+            self.pipeline.set_state(gst::State::Paused)? == gst::StateChangeSuccess::NoPreroll;
+        let capacity = if live { 1 } else { MAX_QUEUED_FRAMES };
+        log::info!("Live source: {live}, queueing up to {capacity} frames");
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        *self.frames.lock().unwrap() = Some(sender);
+
+        let frames = Arc::clone(&self.frames);
+        let eos_frames = Arc::clone(&self.frames);
+        let appsrc = self.appsrc.clone();
+        let mut dropped = 0u64;
+        self.appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    // Encoding takes frames in the same format as decoded, so negotiate it once we know it.
+                    if appsrc.caps().is_none() {
+                        appsrc.set_caps(sample.caps_owned().as_ref());
+                    }
+                    let buffer = sample.buffer_owned().ok_or(gst::FlowError::Error)?;
+                    // Release sample's reference, so the buffer can be written in place.
+                    drop(sample);
+
+                    // Don't hold the lock while blocked on sending, so stopping isn't blocked by it.
+                    let Some(sender) = frames.lock().unwrap().clone() else {
+                        return Err(gst::FlowError::Flushing);
+                    };
+                    if live {
+                        match sender.try_send(buffer) {
+                            Ok(()) => {}
+                            Err(TrySendError::Full(_)) => {
+                                dropped += 1;
+                                log::debug!("Dropped stale frame, {dropped} in total");
+                            }
+                            Err(TrySendError::Disconnected(_)) => {
+                                return Err(gst::FlowError::Flushing)
+                            }
+                        }
+                    } else if sender.send(buffer).is_err() {
+                        return Err(gst::FlowError::Flushing);
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .eos(move |_appsink| {
+                    eos_frames.lock().unwrap().take();
+                })
+                .build(),
+        );
+
+        self.pipeline.set_state(gst::State::Playing)?;
+        Ok(receiver)
+    }
+
+    /// Pushes the processed frame `buffer` into encoding.
+    pub fn push(&self, buffer: gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.appsrc.push_buffer(buffer)
+    }
+
+    /// Signals that all frames have been pushed, so encoding can finish.
+    pub fn end_of_stream(&self) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.appsrc.end_of_stream()
+    }
+
+    /// Blocks until the pipeline reaches the end of stream, or fails.
+    pub fn wait(&self) -> anyhow::Result<()> {
+        let bus = self.pipeline.bus().unwrap();
+        for msg in bus.iter_timed(gst::ClockTime::NONE) {
+            match msg.view() {
+                gst::MessageView::Error(err) => {
+                    self.pipeline
+                        .debug_to_dot_file(gst::DebugGraphDetails::all(), "pipeline.error");
+                    let name = err.src().map(|e| e.name().to_string());
+                    anyhow::bail!("Error from element {name:?}: {}", err.error());
+                }
+                gst::MessageView::Eos(..) => return Ok(()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails the pipeline with the error `message`, which ends [InferencePipeline::wait],
+    /// for errors outside of the pipeline, like failed frame processing.
+    pub fn abort(&self, message: &str) {
+        let error = gst::message::Error::new(gst::CoreError::Failed, message);
+        if self.pipeline.post_message(error).is_err() {
+            log::error!("Failed to post error message: {message}");
+        }
+    }
+
+    /// Stops the pipeline and ends the [Receiver] of decoded frames.
+    pub fn stop(&self) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        // Stop encoding first, which unblocks pushing of processed frames, so processing can pick up
+        // any decoded frame blocked on its way to it, before decoding is stopped too.
+        let _ = self.appsrc.set_state(gst::State::Null);
+        let result = self.pipeline.set_state(gst::State::Null);
+        self.frames.lock().unwrap().take();
+        result
+    }
+}
+
+// src -> decodebin -> appsink ... [inference] ... appsrc -> encode -> mkvmux
+/// Builds gst pipeline that takes input video or stream, decodes it into RGB frames for inference,
+/// and encodes the frames, once they are annotated with inference output, see [InferencePipeline].
 ///
-/// The annotated output is saved into a separate file by default that follows the naming of the `input_file`, but appends `.out.mkv` to filename.
+/// `input` is a file path or an URI, see [is_uri].
+/// The annotated output is saved into a separate file, see [output_path].
 ///
 /// If `live_playback` is enabled, then we create a parallel branch
 /// with a gst `autovideosink`, which usually manages to create a window
 /// with live playback of the annotated output.
 pub fn build_pipeline(
-    input_file: &str,
+    input: &str,
+    live_playback: bool,
+) -> Result<InferencePipeline, glib::BoolError> {
+    build_pipeline_from(src_bin(input)?, &output_path(input), live_playback)
+}
+
+/// Builds [InferencePipeline] decoding frames from `src_bin` and saving the annotated output into `output`.
+fn build_pipeline_from(
+    src_bin: gst::Element,
+    output: &str,
     live_playback: bool,
-    buffer_processor: impl Fn(&mut Buffer) + Send + Sync + 'static,
-) -> Result<gst::Pipeline, glib::BoolError> {
+) -> Result<InferencePipeline, glib::BoolError> {
     let pipeline = gst::Pipeline::new();
 
-    // filesrc -> caps_filter -> video_convert -> [candle] -> queue -> encode -> mkvmux
-    let file_src_bin = file_src_bin(input_file)?;
-    // add video_convert -> caps filter to force RGB buffers
+    // src -> video_convert -> appsink
+    // add video_convert -> appsink caps to force RGB buffers
     // NB! If we use cuda device, use nvidia magic videoconvert at least once in pipeline
     // so we can handle laptop scenarios (with built-in graphics + cuda).
     let converter_factory = if let Some(factory) = gst::ElementFactory::find("nvvideoconvert") {
@@ -77,21 +252,24 @@ pub fn build_pipeline(
     let caps = gst::caps::Caps::builder(glib::gstr!("video/x-raw"))
         .field("format", "RGB")
         .build();
-    let caps_filter = gst::ElementFactory::make_with_name("capsfilter", None)?;
-    caps_filter.set_property("caps", &caps);
+    // Frames are handed over as fast as they are processed, no need to sync them to the clock.
+    let appsink = AppSink::builder().caps(&caps).sync(false).build();
 
-    let queue = gst::ElementFactory::make_with_name("queue", None)?;
-    // perform inference between file_src_bin and queue using a probe on queue src pad
-    let queue_src = queue.static_pad("src").unwrap();
-    // println!("queue_src caps: {:?}", queue_src.caps());
-    queue_src.add_probe(PadProbeType::BUFFER, move |_pad, pad_probe_info| {
-        // we're interested in the buffer
-        if let Some(PadProbeData::Buffer(buffer)) = &mut pad_probe_info.data {
-            buffer_processor(buffer);
-        }
+    let decode_elements = [
+        &src_bin,
+        &video_convert,
+        appsink.upcast_ref::<gst::Element>(),
+    ];
+    pipeline.add_many(decode_elements)?;
+    gst::Element::link_many(decode_elements)?;
 
-        PadProbeReturn::Ok
-    });
+    // appsrc -> encode -> mkvmux
+    // Caps are set from the first decoded frame, see InferencePipeline::play.
+    // Pushing blocks while encoding is behind, instead of queueing up frames.
+    let appsrc = AppSrc::builder()
+        .format(gst::Format::Time)
+        .block(true)
+        .build();
 
     let encoder_convert = gst::ElementFactory::make_with_name("videoconvert", None)?;
     // let encoder_factory =
@@ -107,8 +285,7 @@ pub fn build_pipeline(
     encoder.set_property_from_str("bitrate", "8192");
     let mkv_mux = gst::ElementFactory::make_with_name("matroskamux", None)?;
     let file_sink = gst::ElementFactory::make_with_name("filesink", None)?;
-    let output_path = format!("{input_file}.out.mkv");
-    file_sink.set_property_from_str("location", &output_path);
+    file_sink.set_property_from_str("location", output);
 
     // FIXME live playback branch in parallel with encoding has very bad performance,
     //  whereas standalone it worked fine.
@@ -123,7 +300,7 @@ pub fn build_pipeline(
         let display_sink = gst::ElementFactory::make_with_name("autovideosink", None)?;
 
         // Add and link up to tee
-        let elements_to_tee = [&file_src_bin, &video_convert, &caps_filter, &queue, &tee];
+        let elements_to_tee = [appsrc.upcast_ref::<gst::Element>(), &tee];
         pipeline.add_many(elements_to_tee)?;
         gst::Element::link_many(elements_to_tee)?;
 
@@ -150,10 +327,7 @@ pub fn build_pipeline(
     } else {
         // No live playback, so just wire everything through encoded output.
         let elements = [
-            &file_src_bin,
-            &video_convert,
-            &caps_filter,
-            &queue,
+            appsrc.upcast_ref::<gst::Element>(),
             &encoder_convert,
             &encoder,
             &mkv_mux,
@@ -163,5 +337,46 @@ pub fn build_pipeline(
         gst::Element::link_many(elements)?;
     }
 
-    Ok(pipeline)
+    Ok(InferencePipeline {
+        pipeline,
+        appsink,
+        appsrc,
+        frames: Arc::new(Mutex::new(None)),
+    })
+}
+
+#[test]
+fn output_path_names_uri_outputs_after_stream() {
+    assert_eq!(output_path("data/video.mp4"), "data/video.mp4.out.mkv");
+    assert_eq!(
+        output_path("rtsp://user@camera:554/live/main?tcp"),
+        "main.out.mkv"
+    );
+    assert_eq!(output_path("v4l2:///dev/video0"), "video0.out.mkv");
+    assert_eq!(output_path("rtsp://camera/"), "camera.out.mkv");
+}
+
+#[test]
+fn play_drops_stale_frames_of_live_sources() {
+    gst::init().unwrap();
+    let src =
+        gst::parse::bin_from_description("videotestsrc is-live=true num-buffers=30", true).unwrap();
+    let output = std::env::temp_dir().join("gstreamed_live_source.out.mkv");
+    let pipeline = build_pipeline_from(src.upcast(), output.to_str().unwrap(), false).unwrap();
+    let frames = pipeline.play().unwrap();
+
+    // Don't pick up frames until the source is done, like a very slow inference.
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    let mut received = 0;
+    while frames
+        .recv_timeout(std::time::Duration::from_secs(1))
+        .is_ok()
+    {
+        received += 1;
+    }
+    pipeline.stop().unwrap();
+    let _ = std::fs::remove_file(output);
+
+    // Only the frame queued for processing is kept, the rest is dropped instead of blocking the source.
+    assert!((1..30).contains(&received), "received {received} frames");
 }
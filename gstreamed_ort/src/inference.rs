@@ -1,172 +1,350 @@
+use std::path::Path;
 use std::time::Instant;
 
-use fast_image_resize::{ResizeOptions, Resizer};
 use gstreamed_common::{
-    annotate::annotate_image_with_bboxes, bbox::Bbox, coco_classes, frame_times::FrameTimes,
+    bbox::Detections,
+    detector::Detector,
+    frame_times::FrameTimes,
     img_dimensions::ImgDimensions,
+    labels::LabelMap,
+    letterbox::{letterbox, scale_fill, LetterboxTransform},
 };
-use gstreamed_tracker::{similari::prelude::Sort, unflatten_bboxes};
-use image::{DynamicImage, GenericImageView, RgbImage};
+use image::{DynamicImage, GenericImageView};
 use ndarray::{Array, Array4, CowArray};
+use ort::{
+    CPUExecutionProvider, CUDAExecutionProvider, GraphOptimizationLevel, Session, SessionBuilder,
+};
 
-use crate::yolo_parser::parse_predictions;
+use crate::model_shape::ModelShape;
+use crate::output_layout::{family_from_metadata, layout_from_metadata, ModelFamily, OutputLayout};
+use crate::{rtdetr_parser, yolo_parser};
 
-/// Transforms the input `image` by converting colors, resizing and loading the image buffer into an [Array].
+/// Transforms the input `images` by converting colors, letterboxing and loading them into a single batch [Array].
 ///
-/// Returns the scaled image inside ndarray [Array4] and scaled dims inside [ImgDimensions].
-fn preprocess_image(
-    image: &DynamicImage,
+/// With `stretch`, images are stretched to `target_dims` without padding instead, see [scale_fill].
+/// The batch is padded with blank images up to `batch_size`, for models with a fixed batch size.
+/// Returns the batch inside ndarray [Array4] and the [LetterboxTransform] of each image to map bboxes back.
+fn preprocess_images(
+    images: &[&DynamicImage],
     target_dims: ImgDimensions,
-) -> anyhow::Result<(Array4<f32>, ImgDimensions)> {
-    log::debug!("image.dimensions: {:?}", image.dimensions());
-    log::debug!("image.color: {:?}", image.color());
-
-    // Convert image to rgb8 to ensure pixel values are just that.
-    let image = image.to_rgb8();
-
-    // Resize image to our target size.
-    // Target size is not the model input size, but based on the smallest ratio between input and target dims.
-    let og_dims: ImgDimensions = image.dimensions().into();
-    let ratio = (target_dims.width / og_dims.width).min(target_dims.height / og_dims.height);
-    log::debug!("scale ratio: {ratio:?}");
-    let scaled_dims = og_dims.scale(ratio);
-
-    // Use `fast_image_resize` crate to resize the image.
-    // It has unsafe, but it is way faster than plain `image`, unfortunately...
-    let mut scaled_image = fast_image_resize::images::Image::new(
-        scaled_dims.width as u32,
-        scaled_dims.height as u32,
-        fast_image_resize::PixelType::U8x3,
-    );
-
-    let mut resizer = Resizer::new();
-
-    let image = DynamicImage::ImageRgb8(image);
-    resizer.resize(
-        &image,
-        &mut scaled_image,
-        &ResizeOptions::new().resize_alg(fast_image_resize::ResizeAlg::Nearest),
-    )?;
-
-    let scaled_image = RgbImage::from_raw(
-        scaled_dims.width as u32,
-        scaled_dims.height as u32,
-        scaled_image.into_vec(),
-    )
-    .unwrap();
-
-    // FIXME resize with image crate below is way slower than fast image resize above
-    // let scaled_image = image::imageops::resize(
-    //     &image,
-    //     scaled_dims.width as u32,
-    //     scaled_dims.height as u32,
-    //     image::imageops::FilterType::Nearest,
-    // );
-    log::debug!("scaled_image.dimensions: {:?}", scaled_image.dimensions());
-
-    // Load it into ndarray.
+    batch_size: usize,
+    stretch: bool,
+) -> anyhow::Result<(Array4<f32>, Vec<LetterboxTransform>)> {
     // Array shape: [bsz, channels, height, width];
     let target_shape = [
-        1,
+        batch_size,
         3,
         target_dims.height as usize,
         target_dims.width as usize,
     ];
     let mut image_array = Array::zeros(target_shape);
+    let mut transforms = Vec::with_capacity(images.len());
+
+    for (index, image) in images.iter().enumerate() {
+        log::debug!("image.dimensions: {:?}", image.dimensions());
+        log::debug!("image.color: {:?}", image.color());
+
+        // Letterbox image into our target size, which is the model input size.
+        let (letterboxed, transform) = if stretch {
+            scale_fill(image, target_dims)?
+        } else {
+            letterbox(image, target_dims, None)?
+        };
+        log::debug!("letterboxed.dimensions: {:?}", letterboxed.dimensions());
+
+        // Load it into ndarray.
+        for (x, y, rgb) in letterboxed.enumerate_pixels() {
+            let x = x as usize;
+            let y = y as usize;
+            let [r, g, b] = rgb.0;
+            image_array[[index, 0, y, x]] = (r as f32) / 255.0;
+            image_array[[index, 1, y, x]] = (g as f32) / 255.0;
+            image_array[[index, 2, y, x]] = (b as f32) / 255.0;
+        }
+        transforms.push(transform);
+    }
+
+    Ok((image_array, transforms))
+}
+
+/// Loads the given onnx `model` into an ort [Session], using `cuda` execution provider if requested.
+pub fn load_session(model: &Path, cuda: bool) -> anyhow::Result<Session> {
+    let (ep, ep_name) = if cuda {
+        (CUDAExecutionProvider::default().build(), "cuda")
+    } else {
+        (CPUExecutionProvider::default().build(), "cpu")
+    };
+    // TODO test trt exec provider, but requires a rebuild of onnxruntime with trt enabled
+    // TODO warmup with synthetic image of the same dims?
+
+    ort::init().with_execution_providers([ep]).commit()?;
+
+    let session = SessionBuilder::new()?
+        .with_optimization_level(GraphOptimizationLevel::Level3)?
+        // .with_intra_threads(1)?
+        .commit_from_file(model)?;
+    log::debug!("{session:?}");
 
-    // Copy over the pixels starting from the top
-    for (x, y, rgb) in scaled_image.enumerate_pixels() {
-        let x = x as usize;
-        let y = y as usize;
-        let [r, g, b] = rgb.0;
-        image_array[[0, 0, y, x]] = (r as f32) / 255.0;
-        image_array[[0, 1, y, x]] = (g as f32) / 255.0;
-        image_array[[0, 2, y, x]] = (b as f32) / 255.0;
+    log::info!("Prepared ort {ep_name} session with model: {model:?}");
+    Ok(session)
+}
+
+/// Prints inputs, outputs and metadata of the model loaded into the given [Session].
+pub fn print_session_info(session: &Session) -> anyhow::Result<()> {
+    println!("Inputs:");
+    for input in &session.inputs {
+        println!("  {}: {:?}", input.name, input.input_type);
+    }
+    println!("Outputs:");
+    for output in &session.outputs {
+        println!("  {}: {:?}", output.name, output.output_type);
     }
 
-    Ok((image_array, scaled_dims))
+    match ModelShape::from_session(session) {
+        Ok(shape) => println!("Shape: {shape:?}"),
+        Err(err) => println!("Unsupported model: {err}"),
+    }
+    let labels = match labels_from_metadata(session) {
+        Ok(Some(labels)) => {
+            println!("Labels: {:?}", labels.names());
+            labels
+        }
+        Ok(None) => {
+            println!("Labels: none");
+            LabelMap::coco()
+        }
+        Err(err) => {
+            println!("Labels: failed to parse: {err}");
+            LabelMap::coco()
+        }
+    };
+    let family = family_from_metadata(session);
+    match &family {
+        Ok(family) => println!("Model family: {:?}", family.unwrap_or(ModelFamily::Yolo)),
+        Err(err) => println!("Model family: failed to read: {err}"),
+    }
+    if let (Ok(shape), Ok(None | Some(ModelFamily::Yolo))) =
+        (ModelShape::from_session(session), family)
+    {
+        let layout = layout_from_metadata(session)
+            .and_then(|hint| OutputLayout::detect(shape.output_dims, labels.len(), hint));
+        match layout {
+            Ok(Some(layout)) => println!("Output layout: {layout:?}"),
+            Ok(None) => println!("Output layout: determined from the first output"),
+            Err(err) => println!("Output layout: {err}"),
+        }
+    }
+
+    let metadata = session.metadata()?;
+    println!("Name: {}", metadata.name()?);
+    println!("Producer: {}", metadata.producer()?);
+    println!("Description: {}", metadata.description()?);
+    println!("Version: {}", metadata.version()?);
+
+    Ok(())
+}
+
+/// Yolo or RT-DETR [Detector] running via onnxruntime.
+pub struct OrtDetector {
+    session: Session,
+    shape: ModelShape,
+    family: ModelFamily,
+    /// Output layout of yolo models, `None` if it's only known once the output dims are.
+    layout: Option<OutputLayout>,
+    /// Output layout implied by model metadata, for detecting it from the output dims.
+    layout_hint: Option<OutputLayout>,
+    labels: LabelMap,
+    conf_threshold: f32,
+    nms_threshold: f32,
 }
 
-pub fn infer_on_image(
-    session: &ort::Session,
-    tracker: Option<&mut Sort>,
-    og_image: DynamicImage,
-    frame_times: &mut FrameTimes,
-) -> anyhow::Result<DynamicImage> {
-    // FIXME determine target_dims based on model?
-    let model_input_dims = ImgDimensions::new(640f32, 384f32);
-
-    let start = Instant::now();
-    let (scaled_image_array, scaled_dims) = preprocess_image(&og_image, model_input_dims)?;
-    frame_times.buffer_resize = start.elapsed();
-
-    // Load image into ndarray, and that into ort.
-    let start = Instant::now();
-    let scaled_image_array = CowArray::from(scaled_image_array).into_dyn();
-    log::debug!("image_array.shape: {:?}", scaled_image_array.shape());
-    log::debug!("image_array.strides: {:?}", scaled_image_array.strides());
-
-    let input = ort::inputs![&scaled_image_array]?;
-    frame_times.buffer_to_tensor = start.elapsed();
-
-    // Now, we can finally run inference.
-    let start = Instant::now();
-    let outputs = session.run(input)?;
-    let outputs = outputs[0].try_extract_tensor()?;
-    frame_times.forward_pass = start.elapsed();
-    // output shape is 1 x 84 x 5040
-    // AKA [bsz, embedding, anchors]
-    // embedding is 4 bbox "coords" (center_x, center_y, width, height) + 80 COCO classes long
-    log::debug!("got outputs: {outputs:?}");
-
-    // Parse and annotate outputs.
-    let conf_threshold = 0.25;
-    let nms_threshold = 0.45;
-    let bboxes = parse_predictions(
-        outputs,
-        scaled_dims,
-        coco_classes::NAMES.len() as u32,
-        conf_threshold,
-        nms_threshold,
-        frame_times,
-    )?;
-    log::debug!("{bboxes:?}");
-    log::debug!(
-        "after nms bboxes, len: {:?}",
-        bboxes.iter().map(|v| v.len()).sum::<usize>()
-    );
-
-    // Perform tracking.
-    let mut tracked_bboxes: Option<Vec<Bbox>> = None;
-    if let Some(tracker) = tracker {
+/// Reads class names from `names` metadata embedded by Ultralytics onnx export, if present.
+pub fn labels_from_metadata(session: &Session) -> anyhow::Result<Option<LabelMap>> {
+    match session.metadata()?.custom("names")? {
+        Some(names) => Ok(Some(LabelMap::from_ultralytics_metadata(&names)?)),
+        None => Ok(None),
+    }
+}
+
+impl OrtDetector {
+    /// Wraps the `session` into a detector, failing if the model inputs/outputs are not supported.
+    ///
+    /// If `labels` are not given, they're read from model metadata, falling back to COCO classes.
+    /// If `family` is not given, it's read from model metadata, falling back to [ModelFamily::Yolo].
+    /// If `layout` of a yolo model is not given, it's detected from the output shape and model metadata,
+    /// see [OutputLayout::detect].
+    pub fn new(
+        session: Session,
+        labels: Option<LabelMap>,
+        family: Option<ModelFamily>,
+        layout: Option<OutputLayout>,
+        conf_threshold: f32,
+        nms_threshold: f32,
+    ) -> anyhow::Result<Self> {
+        let shape = ModelShape::from_session(&session)?;
+        log::info!("Model shape: {shape:?}");
+
+        let labels = match labels {
+            Some(labels) => labels,
+            None => labels_from_metadata(&session)?.unwrap_or_else(|| {
+                log::warn!("Model has no class names in metadata, assuming COCO classes");
+                LabelMap::coco()
+            }),
+        };
+        let family = match family {
+            Some(family) => family,
+            None => family_from_metadata(&session)?.unwrap_or(ModelFamily::Yolo),
+        };
+        log::info!("Model family: {family:?}");
+        let layout_hint = layout_from_metadata(&session)?;
+        let layout = match (family, layout) {
+            (ModelFamily::RtDetr | ModelFamily::RtDetrLogits, Some(_)) => {
+                anyhow::bail!("Output layout applies to yolo models only, not RT-DETR")
+            }
+            (ModelFamily::RtDetr | ModelFamily::RtDetrLogits, None) => {
+                rtdetr_parser::check_dims(shape.output_dims, labels.len())?;
+                None
+            }
+            (ModelFamily::Yolo, Some(layout)) => {
+                layout.check_dims(shape.output_dims, labels.len())?;
+                Some(layout)
+            }
+            (ModelFamily::Yolo, None) => {
+                OutputLayout::detect(shape.output_dims, labels.len(), layout_hint)?
+            }
+        };
+        log::info!("Model output layout: {layout:?}");
+
+        Ok(Self {
+            session,
+            shape,
+            family,
+            layout,
+            layout_hint,
+            labels,
+            conf_threshold,
+            nms_threshold,
+        })
+    }
+}
+
+impl OrtDetector {
+    /// Runs a single forward pass on `images`, letterboxed (or stretched for RT-DETR) into `input_dims`
+    /// and padded up to `batch_size`.
+    ///
+    /// Returns detections of each image, adding stage times to `frame_times`.
+    fn detect_chunk(
+        &self,
+        images: &[&DynamicImage],
+        input_dims: ImgDimensions,
+        batch_size: usize,
+        frame_times: &mut FrameTimes,
+    ) -> anyhow::Result<Vec<Detections>> {
         let start = Instant::now();
-        tracked_bboxes = Some(gstreamed_tracker::predict_tracked_bboxes(
-            tracker,
-            scaled_dims,
-            &bboxes,
-        ));
-        frame_times.tracking = start.elapsed();
+        let (scaled_image_array, transforms) =
+            preprocess_images(images, input_dims, batch_size, self.family.scale_fill())?;
+        frame_times.buffer_resize += start.elapsed();
+
+        // Load image into ndarray, and that into ort.
+        let start = Instant::now();
+        let scaled_image_array = CowArray::from(scaled_image_array).into_dyn();
+        log::debug!("image_array.shape: {:?}", scaled_image_array.shape());
+        log::debug!("image_array.strides: {:?}", scaled_image_array.strides());
+
+        let input = ort::inputs![&scaled_image_array]?;
+        frame_times.buffer_to_tensor += start.elapsed();
+
+        // Now, we can finally run inference.
+        let start = Instant::now();
+        let outputs = self.session.run(input)?;
+        let outputs = outputs[0].try_extract_tensor()?;
+        frame_times.forward_pass += start.elapsed();
+        // output shape is bsz x 84 x 5040 for v8 layout
+        // AKA [bsz, embedding, anchors]
+        // embedding is 4 bbox "coords" (center_x, center_y, width, height) + 80 COCO classes long
+        log::debug!("got outputs: {outputs:?}");
+        let num_classes = self.labels.len();
+        if self.family != ModelFamily::Yolo {
+            let batch = rtdetr_parser::parse_predictions(
+                outputs,
+                &transforms,
+                num_classes as u32,
+                self.family == ModelFamily::RtDetrLogits,
+                self.conf_threshold,
+                frame_times,
+            )?;
+            log::debug!("{batch:?}");
+            return Ok(batch);
+        }
+        let layout = match self.layout {
+            Some(layout) => layout,
+            None => {
+                let &[_bsz, d1, d2] = outputs.shape() else {
+                    anyhow::bail!(
+                        "Model output must have shape [bsz, d1, d2], got: {:?}",
+                        outputs.shape()
+                    );
+                };
+                let dims = [Some(d1), Some(d2)];
+                OutputLayout::detect(dims, num_classes, self.layout_hint)?.ok_or_else(|| {
+                    anyhow::anyhow!("Failed to determine layout of model output {dims:?}")
+                })?
+            }
+        };
+
+        // Parse outputs of each image, mapping bboxes back to the original image coordinates.
+        let batch = yolo_parser::parse_predictions(
+            outputs,
+            layout,
+            &transforms,
+            num_classes as u32,
+            self.conf_threshold,
+            self.nms_threshold,
+            frame_times,
+        )?;
+        log::debug!("{batch:?}");
+
+        Ok(batch)
+    }
+}
+
+impl Detector for OrtDetector {
+    fn detect(&self, og_image: &DynamicImage) -> anyhow::Result<(Detections, FrameTimes)> {
+        let (mut batch, frame_times) = self.detect_batch(&[og_image])?;
+        Ok((batch.pop().unwrap(), frame_times))
     }
-    log::debug!("{tracked_bboxes:?}");
 
-    // Annotate the original image and print boxes information.
-    let start = Instant::now();
-    let legend_size = 14;
+    /// Runs the whole batch in a single forward pass, or in passes of the fixed model batch size.
+    ///
+    /// Images are letterboxed into the same input dimensions, those of the first image for dynamic models.
+    fn detect_batch(
+        &self,
+        images: &[&DynamicImage],
+    ) -> anyhow::Result<(Vec<Detections>, FrameTimes)> {
+        let mut frame_times = FrameTimes::default();
+        let Some(first) = images.first() else {
+            return Ok((vec![], frame_times));
+        };
+        let model_input_dims = self.shape.input_dims(first.dimensions().into());
+        let batch_size = self
+            .shape
+            .batch_size
+            .map_or(images.len(), |batch_size| batch_size as usize);
 
-    // Map tracked bboxes back to per class bbox vec...
-    let bboxes = match tracked_bboxes {
-        Some(tracked) => unflatten_bboxes(tracked),
-        None => bboxes,
-    };
+        let mut batch = Vec::with_capacity(images.len());
+        for chunk in images.chunks(batch_size) {
+            batch.extend(self.detect_chunk(
+                chunk,
+                model_input_dims,
+                batch_size,
+                &mut frame_times,
+            )?);
+        }
 
-    let annotated = annotate_image_with_bboxes(
-        og_image,
-        scaled_dims.width as usize,
-        scaled_dims.height as usize,
-        legend_size,
-        &bboxes,
-    );
-    frame_times.annotation = start.elapsed();
+        Ok((batch, frame_times))
+    }
 
-    Ok(annotated)
+    fn labels(&self) -> &LabelMap {
+        &self.labels
+    }
 }
@@ -0,0 +1,11 @@
+//! Yolo and RT-DETR object detection via onnxruntime, using `ort` library.
+
+pub mod inference;
+pub mod model_shape;
+pub mod output_layout;
+pub mod reid;
+pub mod rtdetr_parser;
+pub mod yolo_parser;
+
+pub use inference::OrtDetector;
+pub use reid::OrtEmbedder;
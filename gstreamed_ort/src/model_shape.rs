@@ -0,0 +1,157 @@
+//! Model input/output tensor shapes, as read from onnx model metadata.
+
+use gstreamed_common::img_dimensions::ImgDimensions;
+use ort::{Session, TensorElementType, ValueType};
+
+/// Model input sizes have to be divisible by the largest yolov8 stride.
+pub const STRIDE: u32 = 32;
+
+/// Size of the longer input side used for models with dynamic input width/height.
+pub const DEFAULT_DYNAMIC_SIZE: u32 = 640;
+
+/// Input and output shapes of a yolo-style onnx detection model.
+///
+/// Dynamic axes are `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelShape {
+    /// Batch size, from `[bsz, 3, height, width]`.
+    pub batch_size: Option<u32>,
+    /// Input width, from `[bsz, 3, height, width]`.
+    pub input_width: Option<u32>,
+    /// Input height, from `[bsz, 3, height, width]`.
+    pub input_height: Option<u32>,
+    /// Output dims after the batch one, from `[bsz, d1, d2]` output, see [crate::output_layout::OutputLayout].
+    pub output_dims: [Option<usize>; 2],
+}
+
+/// Maps onnx dimension into `Some(dim)` for fixed dimensions and `None` for dynamic ones.
+pub(crate) fn fixed_dim(dim: i64) -> Option<u32> {
+    (dim > 0).then_some(dim as u32)
+}
+
+pub(crate) fn tensor_dims<'a>(
+    kind: &str,
+    name: &str,
+    value_type: &'a ValueType,
+) -> anyhow::Result<&'a Vec<i64>> {
+    match value_type {
+        ValueType::Tensor {
+            ty: TensorElementType::Float32,
+            dimensions,
+        } => Ok(dimensions),
+        other => anyhow::bail!("Model {kind} {name:?} must be a f32 tensor, got: {other:?}"),
+    }
+}
+
+impl ModelShape {
+    /// Reads input and output shapes from the [Session], refusing models we can't feed.
+    pub fn from_session(session: &Session) -> anyhow::Result<Self> {
+        let [input] = session.inputs.as_slice() else {
+            anyhow::bail!(
+                "Model must have exactly 1 image input, got {}",
+                session.inputs.len()
+            );
+        };
+        let dims = tensor_dims("input", &input.name, &input.input_type)?;
+        let &[bsz, channels, height, width] = dims.as_slice() else {
+            anyhow::bail!(
+                "Model input {:?} must have shape [bsz, 3, height, width], got: {dims:?}",
+                input.name
+            );
+        };
+        anyhow::ensure!(
+            fixed_dim(channels).unwrap_or(3) == 3,
+            "Model input {:?} must have 3 (RGB) channels, got {channels}",
+            input.name
+        );
+        let input_width = fixed_dim(width);
+        let input_height = fixed_dim(height);
+        for side in [input_width, input_height].into_iter().flatten() {
+            anyhow::ensure!(
+                side % STRIDE == 0,
+                "Model input {:?} size must be divisible by {STRIDE}, got: {dims:?}",
+                input.name
+            );
+        }
+
+        let Some(output) = session.outputs.first() else {
+            anyhow::bail!("Model has no outputs");
+        };
+        let dims = tensor_dims("output", &output.name, &output.output_type)?;
+        let &[_bsz, d1, d2] = dims.as_slice() else {
+            anyhow::bail!(
+                "Model output {:?} must have shape [bsz, d1, d2], got: {dims:?}",
+                output.name
+            );
+        };
+        let output_dims = [d1, d2].map(|dim| fixed_dim(dim).map(|dim| dim as usize));
+
+        Ok(Self {
+            batch_size: fixed_dim(bsz),
+            input_width,
+            input_height,
+            output_dims,
+        })
+    }
+
+    /// Determines model input dimensions to use for an image of `image_dims`.
+    ///
+    /// Fixed model dimensions are used as is, while dynamic ones are chosen
+    /// to keep the aspect ratio of the image, aligned up to [STRIDE].
+    pub fn input_dims(&self, image_dims: ImgDimensions) -> ImgDimensions {
+        let ratio = match (self.input_width, self.input_height) {
+            (Some(width), Some(height)) => {
+                return ImgDimensions::new(width as f32, height as f32);
+            }
+            (Some(width), None) => width as f32 / image_dims.width,
+            (None, Some(height)) => height as f32 / image_dims.height,
+            (None, None) => DEFAULT_DYNAMIC_SIZE as f32 / image_dims.width.max(image_dims.height),
+        };
+        let align = |side: f32| ((side / STRIDE as f32).ceil() as u32 * STRIDE) as f32;
+        let scaled = image_dims.scale(ratio);
+        ImgDimensions::new(
+            self.input_width
+                .map(|w| w as f32)
+                .unwrap_or(align(scaled.width)),
+            self.input_height
+                .map(|h| h as f32)
+                .unwrap_or(align(scaled.height)),
+        )
+    }
+}
+
+#[test]
+fn dynamic_input_dims_keep_aspect_ratio() {
+    let shape = ModelShape {
+        batch_size: None,
+        input_width: None,
+        input_height: None,
+        output_dims: [Some(84), None],
+    };
+    let dims = shape.input_dims(ImgDimensions::new(1280.0, 720.0));
+    assert_eq!(dims, ImgDimensions::new(640.0, 384.0));
+
+    let dims = shape.input_dims(ImgDimensions::new(1080.0, 1920.0));
+    assert_eq!(dims, ImgDimensions::new(384.0, 640.0));
+}
+
+#[test]
+fn fixed_input_dims_are_used_as_is() {
+    let shape = ModelShape {
+        batch_size: Some(1),
+        input_width: Some(640),
+        input_height: Some(640),
+        output_dims: [Some(84), Some(8400)],
+    };
+    let dims = shape.input_dims(ImgDimensions::new(1280.0, 720.0));
+    assert_eq!(dims, ImgDimensions::new(640.0, 640.0));
+
+    let shape = ModelShape {
+        batch_size: None,
+        input_width: Some(1280),
+        input_height: None,
+        output_dims: [None, None],
+    };
+    let dims = shape.input_dims(ImgDimensions::new(1920.0, 1080.0));
+    assert_eq!(dims, ImgDimensions::new(1280.0, 736.0));
+}
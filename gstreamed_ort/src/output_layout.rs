@@ -0,0 +1,206 @@
+//! Layouts of detection model outputs, which differ between YOLO versions and export options.
+
+use clap::ValueEnum;
+use ort::Session;
+
+/// Family of the detection model, which determines how its output is decoded.
+///
+/// RT-DETR output `[bsz, queries, 4 + classes]` has the same shape as [OutputLayout::V8Transposed],
+/// so the family can't be told from the shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ModelFamily {
+    /// YOLO models, in one of [OutputLayout]s, decoded by [crate::yolo_parser].
+    Yolo,
+    /// RT-DETR transformer models, decoded by [crate::rtdetr_parser], with class scores as exported by Ultralytics.
+    RtDetr,
+    /// RT-DETR models exported with raw class logits, which are passed through sigmoid.
+    RtDetrLogits,
+}
+
+impl ModelFamily {
+    /// Whether images are stretched to the model input instead of letterboxed, as Ultralytics does for RT-DETR.
+    pub fn scale_fill(self) -> bool {
+        self != ModelFamily::Yolo
+    }
+}
+
+/// Layout of the `[bsz, d1, d2]` detection model output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputLayout {
+    /// `[bsz, 4 + classes, anchors]`, cxcywh bboxes followed by class scores,
+    /// as exported by Ultralytics YOLOv8 and later.
+    V8,
+    /// `[bsz, anchors, 4 + classes]`, transposed [OutputLayout::V8].
+    V8Transposed,
+    /// `[bsz, anchors, 5 + classes]`, cxcywh bboxes, objectness and class scores, as exported by YOLOv5.
+    V5,
+    /// `[bsz, max_det, 6]`, final `x1, y1, x2, y2, score, class` detections of end-to-end (NMS free) models.
+    EndToEnd,
+}
+
+/// All layouts, in the order they are tried in.
+const LAYOUTS: [OutputLayout; 4] = [
+    OutputLayout::V8,
+    OutputLayout::V8Transposed,
+    OutputLayout::V5,
+    OutputLayout::EndToEnd,
+];
+
+impl OutputLayout {
+    /// Expected shape of the layout, for error messages.
+    fn shape(self) -> &'static str {
+        match self {
+            OutputLayout::V8 => "[bsz, 4 + classes, anchors]",
+            OutputLayout::V8Transposed => "[bsz, anchors, 4 + classes]",
+            OutputLayout::V5 => "[bsz, anchors, 5 + classes]",
+            OutputLayout::EndToEnd => "[bsz, max_det, 6]",
+        }
+    }
+
+    /// Picks the dim of output dims `[d1, d2]` after the batch dim, which has a known size in the layout,
+    /// returning it with the size expected for `num_classes`.
+    fn known_dim(self, [d1, d2]: [Option<usize>; 2], num_classes: usize) -> (Option<usize>, usize) {
+        match self {
+            OutputLayout::V8 => (d1, 4 + num_classes),
+            OutputLayout::V8Transposed => (d2, 4 + num_classes),
+            OutputLayout::V5 => (d2, 5 + num_classes),
+            OutputLayout::EndToEnd => (d2, 6),
+        }
+    }
+
+    /// Whether output `dims` fit the layout with `num_classes`, dynamic dims (`None`) fit anything.
+    fn fits(self, dims: [Option<usize>; 2], num_classes: usize) -> bool {
+        let (dim, expected) = self.known_dim(dims, num_classes);
+        dim.is_none_or(|dim| dim == expected)
+    }
+
+    /// Whether output `dims` fit the layout with `num_classes`, with the known dim being fixed.
+    fn fits_fixed(self, dims: [Option<usize>; 2], num_classes: usize) -> bool {
+        let (dim, expected) = self.known_dim(dims, num_classes);
+        dim == Some(expected)
+    }
+
+    /// Determines the layout of output `dims` `[d1, d2]` after the batch dim, for a model with `num_classes`.
+    ///
+    /// `hint` (e.g. from model metadata) is used if it fits, resolving ambiguous dims, like `[8400, 6]`
+    /// of a 2 class transposed model vs. an end-to-end one.
+    /// Returns `None` if dynamic dims leave the layout open, so it has to be determined from the actual output.
+    pub fn detect(
+        dims: [Option<usize>; 2],
+        num_classes: usize,
+        hint: Option<OutputLayout>,
+    ) -> anyhow::Result<Option<Self>> {
+        if let Some(hint) = hint.filter(|hint| hint.fits(dims, num_classes)) {
+            return Ok(Some(hint));
+        }
+        let candidates: Vec<_> = LAYOUTS
+            .into_iter()
+            .filter(|layout| layout.fits_fixed(dims, num_classes))
+            .collect();
+        match candidates.as_slice() {
+            [layout] => Ok(Some(*layout)),
+            [] if dims.contains(&None) => Ok(None),
+            [] => anyhow::bail!(
+                "Model output {} doesn't fit any supported layout with {num_classes} classes: {}. \
+                Check that the labels match the model",
+                format_dims(dims),
+                LAYOUTS.map(|layout| format!("{layout:?} {}", layout.shape())).join(", "),
+            ),
+            _ => anyhow::bail!(
+                "Model output {} with {num_classes} classes is ambiguous, it fits layouts {candidates:?}, \
+                choose one with --output-layout",
+                format_dims(dims)
+            ),
+        }
+    }
+
+    /// Checks that model output `dims` `[d1, d2]` after the batch dim fit the layout with `num_classes`,
+    /// so a wrong layout gives an error rather than garbage bboxes.
+    pub fn check_dims(self, dims: [Option<usize>; 2], num_classes: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.fits(dims, num_classes),
+            "Model output {} doesn't fit {self:?} layout {} with {num_classes} classes",
+            format_dims(dims),
+            self.shape()
+        );
+        Ok(())
+    }
+
+    /// Checks that the actual output `shape` of a forward pass fits the layout with `num_classes`.
+    pub fn check(self, shape: &[usize], num_classes: usize) -> anyhow::Result<()> {
+        let &[_bsz, d1, d2] = shape else {
+            anyhow::bail!("Model output must have shape [bsz, d1, d2], got: {shape:?}");
+        };
+        self.check_dims([Some(d1), Some(d2)], num_classes)
+    }
+}
+
+fn format_dims(dims: [Option<usize>; 2]) -> String {
+    let dims = dims.map(|dim| dim.map_or("?".to_string(), |dim| dim.to_string()));
+    format!("[bsz, {}, {}]", dims[0], dims[1])
+}
+
+/// Reads the model family from model metadata, if recognized.
+///
+/// Only RT-DETR models are recognized, by the model name in the description embedded by Ultralytics export,
+/// e.g. `Ultralytics rtdetr-l model trained on coco.yaml`.
+pub fn family_from_metadata(session: &Session) -> anyhow::Result<Option<ModelFamily>> {
+    let is_rtdetr = session
+        .metadata()?
+        .custom("description")?
+        .is_some_and(|description| {
+            description
+                .to_ascii_lowercase()
+                .replace(['-', '_'], "")
+                .contains("rtdetr")
+        });
+    Ok(is_rtdetr.then_some(ModelFamily::RtDetr))
+}
+
+/// Reads the output layout implied by model metadata, if any.
+///
+/// Only end-to-end models are recognized, by `end2end` metadata, the `nms` export argument
+/// or a YOLOv10 description, embedded by Ultralytics export.
+pub fn layout_from_metadata(session: &Session) -> anyhow::Result<Option<OutputLayout>> {
+    let metadata = session.metadata()?;
+    let is_true =
+        |value: Option<String>| value.is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    let end_to_end = is_true(metadata.custom("end2end")?)
+        || metadata
+            .custom("args")?
+            .is_some_and(|args| args.contains("'nms': True"))
+        || metadata
+            .custom("description")?
+            .is_some_and(|description| description.contains("YOLOv10"));
+    Ok(end_to_end.then_some(OutputLayout::EndToEnd))
+}
+
+#[test]
+fn layout_is_detected_from_output_dims() {
+    let detect = |d1, d2, hint| OutputLayout::detect([d1, d2], 80, hint).unwrap();
+    assert_eq!(detect(Some(84), Some(8400), None), Some(OutputLayout::V8));
+    assert_eq!(detect(Some(84), None, None), Some(OutputLayout::V8));
+    assert_eq!(
+        detect(Some(8400), Some(84), None),
+        Some(OutputLayout::V8Transposed)
+    );
+    assert_eq!(detect(Some(25200), Some(85), None), Some(OutputLayout::V5));
+    assert_eq!(
+        detect(Some(300), Some(6), None),
+        Some(OutputLayout::EndToEnd)
+    );
+    // Fully dynamic outputs are determined from the actual output.
+    assert_eq!(detect(None, None, None), None);
+
+    // Wrong number of classes gives an error.
+    assert!(OutputLayout::detect([Some(84), Some(8400)], 2, None).is_err());
+    // 2 classes transposed vs. end-to-end is ambiguous, unless hinted.
+    assert!(OutputLayout::detect([Some(8400), Some(6)], 2, None).is_err());
+    assert_eq!(
+        OutputLayout::detect([Some(8400), Some(6)], 2, Some(OutputLayout::EndToEnd)).unwrap(),
+        Some(OutputLayout::EndToEnd)
+    );
+
+    assert!(OutputLayout::V8.check(&[1, 84, 8400], 80).is_ok());
+    assert!(OutputLayout::V8.check(&[1, 8400, 84], 80).is_err());
+}
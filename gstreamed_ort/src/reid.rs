@@ -0,0 +1,144 @@
+//! Appearance embeddings for re-identification, using a ReID onnx model (e.g. OSNet from torchreid).
+//!
+//! Detection crops are resized to the model input size, without keeping the aspect ratio,
+//! and normalized with ImageNet mean and std, as ReID models are usually trained.
+
+use gstreamed_common::bbox::Detections;
+use gstreamed_common::embedder::Embedder;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use ndarray::{Array, Array4, CowArray};
+use ort::Session;
+
+use crate::model_shape::{fixed_dim, tensor_dims};
+
+/// Input size used for models with dynamic input width/height, the usual person ReID size.
+pub const DEFAULT_REID_WIDTH: u32 = 128;
+pub const DEFAULT_REID_HEIGHT: u32 = 256;
+
+const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+/// ReID [Embedder] running via onnxruntime.
+pub struct OrtEmbedder {
+    session: Session,
+    input_width: u32,
+    input_height: u32,
+    /// Whether the model accepts any batch size, otherwise crops are embedded one by one.
+    dynamic_batch: bool,
+}
+
+impl OrtEmbedder {
+    /// Wraps the `session` into an embedder, failing if the model input is not `[bsz, 3, height, width]`.
+    pub fn new(session: Session) -> anyhow::Result<Self> {
+        let [input] = session.inputs.as_slice() else {
+            anyhow::bail!(
+                "ReID model must have exactly 1 image input, got {}",
+                session.inputs.len()
+            );
+        };
+        let dims = tensor_dims("input", &input.name, &input.input_type)?;
+        let &[bsz, channels, height, width] = dims.as_slice() else {
+            anyhow::bail!(
+                "ReID model input {:?} must have shape [bsz, 3, height, width], got: {dims:?}",
+                input.name
+            );
+        };
+        anyhow::ensure!(
+            fixed_dim(channels).unwrap_or(3) == 3,
+            "ReID model input {:?} must have 3 (RGB) channels, got {channels}",
+            input.name
+        );
+        // Crops are embedded one by one with a fixed batch size.
+        anyhow::ensure!(
+            fixed_dim(bsz).unwrap_or(1) == 1,
+            "ReID model input {:?} must have a dynamic batch size or a batch size of 1, got {bsz}",
+            input.name
+        );
+        let embedder = Self {
+            input_width: fixed_dim(width).unwrap_or(DEFAULT_REID_WIDTH),
+            input_height: fixed_dim(height).unwrap_or(DEFAULT_REID_HEIGHT),
+            dynamic_batch: fixed_dim(bsz).is_none(),
+            session,
+        };
+        log::info!(
+            "ReID model input: {}x{}, dynamic batch: {}",
+            embedder.input_width,
+            embedder.input_height,
+            embedder.dynamic_batch
+        );
+        Ok(embedder)
+    }
+
+    /// Crops, resizes and normalizes bboxes of `detections` into a `[N, 3, height, width]` batch.
+    fn preprocess(&self, image: &DynamicImage, detections: &Detections) -> Array4<f32> {
+        let (width, height) = (self.input_width, self.input_height);
+        let shape = [detections.len(), 3, height as usize, width as usize];
+        let mut batch = Array::zeros(shape);
+        for (i, bbox) in detections.iter().enumerate() {
+            // Crops are at least a pixel large, even for degenerate bboxes.
+            let xmin = (bbox.xmin.max(0.0) as u32).min(image.width() - 1);
+            let ymin = (bbox.ymin.max(0.0) as u32).min(image.height() - 1);
+            let crop_width = ((bbox.xmax - bbox.xmin) as u32).clamp(1, image.width() - xmin);
+            let crop_height = ((bbox.ymax - bbox.ymin) as u32).clamp(1, image.height() - ymin);
+            let crop = image
+                .crop_imm(xmin, ymin, crop_width, crop_height)
+                .resize_exact(width, height, FilterType::Triangle)
+                .to_rgb8();
+            for (x, y, rgb) in crop.enumerate_pixels() {
+                for c in 0..3 {
+                    batch[[i, c, y as usize, x as usize]] =
+                        (rgb.0[c] as f32 / 255.0 - IMAGENET_MEAN[c]) / IMAGENET_STD[c];
+                }
+            }
+        }
+        batch
+    }
+
+    /// Runs the model on the given `batch`, returning L2 normalized embeddings, one per crop.
+    fn run(&self, batch: Array4<f32>) -> anyhow::Result<Vec<Vec<f32>>> {
+        let num_crops = batch.shape()[0];
+        let batch = CowArray::from(batch).into_dyn();
+        let outputs = self.session.run(ort::inputs![&batch]?)?;
+        let outputs = outputs[0].try_extract_tensor::<f32>()?;
+        anyhow::ensure!(
+            outputs.ndim() == 2 && outputs.shape()[0] == num_crops && outputs.shape()[1] > 0,
+            "ReID model output must have shape [{num_crops}, features], got: {:?}",
+            outputs.shape()
+        );
+        let embeddings = outputs
+            .outer_iter()
+            .map(|embedding| {
+                let norm = embedding
+                    .iter()
+                    .map(|v| v * v)
+                    .sum::<f32>()
+                    .sqrt()
+                    .max(1e-12);
+                embedding.iter().map(|v| v / norm).collect()
+            })
+            .collect();
+        Ok(embeddings)
+    }
+}
+
+impl Embedder for OrtEmbedder {
+    fn embed(
+        &self,
+        image: &DynamicImage,
+        detections: &Detections,
+    ) -> anyhow::Result<Vec<Vec<f32>>> {
+        if detections.is_empty() {
+            return Ok(vec![]);
+        }
+        let batch = self.preprocess(image, detections);
+        if self.dynamic_batch {
+            return self.run(batch);
+        }
+        let mut embeddings = Vec::with_capacity(detections.len());
+        for crop in batch.outer_iter() {
+            embeddings.extend(self.run(crop.insert_axis(ndarray::Axis(0)).to_owned())?);
+        }
+        Ok(embeddings)
+    }
+}
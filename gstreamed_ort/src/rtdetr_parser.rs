@@ -0,0 +1,210 @@
+use std::time::Instant;
+
+use gstreamed_common::{
+    bbox::{Bbox, Detections},
+    frame_times::FrameTimes,
+    img_dimensions::ImgDimensions,
+    letterbox::LetterboxTransform,
+};
+use ndarray::{s, ArrayView, Axis, Dim, IxDyn};
+
+/// Parse RT-DETR predictions via `ort`, for a batch of images.
+///
+/// `preds` are of shape `[bsz, queries, 4 + classes]`: each query has a cxcywh bbox, normalized to the
+/// model input image, followed by class scores, or raw class logits if `logits` is set.
+/// Bboxes are mapped back to the original image with the [LetterboxTransform] of each image in `transforms`,
+/// batch elements beyond them are ignored.
+/// Queries are final detections, so there are no anchors to decode and no NMS.
+///
+/// Returns [Detections] of each image, in the order of `transforms`.
+pub fn parse_predictions(
+    preds: ArrayView<f32, IxDyn>,
+    transforms: &[LetterboxTransform],
+    num_clases: u32,
+    logits: bool,
+    conf_threshold: f32,
+    frame_times: &mut FrameTimes,
+) -> anyhow::Result<Vec<Detections>> {
+    log::debug!("preds.shape: {:?}", preds.shape());
+    let &[bsz, queries, embedding] = preds.shape() else {
+        anyhow::bail!(
+            "Model output must have shape [bsz, queries, 4 + classes], got: {:?}",
+            preds.shape()
+        );
+    };
+    check_dims([Some(queries), Some(embedding)], num_clases as usize)?;
+    anyhow::ensure!(
+        bsz >= transforms.len(),
+        "Expected predictions of {} images, got: {:?}",
+        transforms.len(),
+        preds.shape()
+    );
+
+    let mut batch = Vec::with_capacity(transforms.len());
+    for (index, transform) in transforms.iter().enumerate() {
+        let image_preds: ArrayView<f32, Dim<[usize; 2]>> = preds.slice(s![index, .., ..]);
+        let mut detections = parse_image_predictions(
+            image_preds,
+            transform.letterboxed_dims,
+            num_clases,
+            logits,
+            conf_threshold,
+            frame_times,
+        );
+        // Map bboxes back to the original image coordinates.
+        transform.to_original(&mut detections);
+        batch.push(detections);
+    }
+
+    Ok(batch)
+}
+
+/// Checks that model output `dims` `[queries, 4 + classes]` after the batch dim fit `num_classes`,
+/// dynamic dims (`None`) fit anything.
+pub fn check_dims(
+    [_queries, embedding]: [Option<usize>; 2],
+    num_classes: usize,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        embedding.is_none_or(|embedding| embedding == 4 + num_classes),
+        "RT-DETR model output [bsz, queries, {}] doesn't fit {num_classes} classes, \
+        check that the labels match the model",
+        embedding.map_or("?".to_string(), |embedding| embedding.to_string())
+    );
+    Ok(())
+}
+
+/// Parses predictions of a single image, of shape `[queries, 4 + classes]`, into bboxes in letterboxed coordinates.
+///
+/// Stage times are added to `frame_times`.
+fn parse_image_predictions(
+    preds: ArrayView<f32, Dim<[usize; 2]>>,
+    letterboxed_dims: ImgDimensions,
+    num_clases: u32,
+    logits: bool,
+    conf_threshold: f32,
+    frame_times: &mut FrameTimes,
+) -> Detections {
+    let start = Instant::now();
+    let mut detections = Detections::new(letterboxed_dims);
+    for pred in preds.axis_iter(Axis(0)) {
+        let clss = pred.slice(s![4..4 + num_clases as usize]);
+
+        // Sigmoid is monotonic, so top1 class is the same for scores and logits.
+        let mut max_class_id = 0;
+        let mut max_score = f32::NEG_INFINITY;
+        for (idx, &score) in clss.into_iter().enumerate() {
+            if score > max_score {
+                max_score = score;
+                max_class_id = idx;
+            }
+        }
+        let confidence = if logits {
+            sigmoid(max_score)
+        } else {
+            max_score
+        };
+        if confidence < conf_threshold {
+            continue;
+        }
+
+        let cx = pred[0] * letterboxed_dims.width;
+        let cy = pred[1] * letterboxed_dims.height;
+        let w = pred[2] * letterboxed_dims.width;
+        let h = pred[3] * letterboxed_dims.height;
+
+        // Bound coords to letterboxed dimensions, so bboxes don't go outside the image.
+        let bbox = Bbox {
+            xmin: (cx - w / 2.).max(0.0f32).min(letterboxed_dims.width),
+            ymin: (cy - h / 2.).max(0.0f32).min(letterboxed_dims.height),
+            xmax: (cx + w / 2.).max(0.0f32).min(letterboxed_dims.width),
+            ymax: (cy + h / 2.).max(0.0f32).min(letterboxed_dims.height),
+            detector_confidence: confidence,
+            tracker_confidence: 0f32,
+            data: vec![],
+            class: max_class_id,
+            tracker_id: None,
+            observed: None,
+            predicted: false,
+        };
+        detections.push(bbox);
+    }
+    frame_times.bbox_extraction += start.elapsed();
+
+    detections
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[test]
+fn parse_predictions_decodes_normalized_queries() {
+    use ndarray::Array3;
+
+    let target = ImgDimensions::new(640.0, 640.0);
+    let transforms = [LetterboxTransform::scale_fill(
+        ImgDimensions::new(1280.0, 720.0),
+        target,
+    )];
+    // [bsz, 3 queries, 4 + 2 classes], two overlapping class 1 queries, kept without NMS,
+    // and one below the confidence threshold.
+    let mut preds = Array3::<f32>::zeros((1, 3, 6));
+    for (query, values) in [
+        [0.5, 0.5, 0.15625, 0.078125, 0.02, 0.9],
+        [0.51, 0.5, 0.15625, 0.078125, 0.02, 0.7],
+        [0.2, 0.2, 0.1, 0.1, 0.05, 0.1],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        for (col, value) in values.into_iter().enumerate() {
+            preds[[0, query, col]] = value;
+        }
+    }
+
+    let batch = parse_predictions(
+        preds.view().into_dyn(),
+        &transforms,
+        2,
+        false,
+        0.25,
+        &mut FrameTimes::default(),
+    )
+    .unwrap();
+    let [first, second] = batch[0].as_slice() else {
+        panic!("Expected 2 bboxes, got {:?}", batch[0]);
+    };
+    assert_eq!(first.class, 1);
+    assert_eq!(first.detector_confidence, 0.9);
+    // 100x50 bbox at the center of the stretched image, scaled back by 2 horizontally and 1.125 vertically.
+    assert_eq!(
+        (first.xmin, first.ymin, first.xmax, first.ymax),
+        (540.0, 331.875, 740.0, 388.125)
+    );
+    assert_eq!(second.class, 1);
+
+    // With the logits option, scores go through sigmoid, so all queries pass the threshold.
+    let batch = parse_predictions(
+        preds.view().into_dyn(),
+        &transforms,
+        2,
+        true,
+        0.25,
+        &mut FrameTimes::default(),
+    )
+    .unwrap();
+    assert_eq!(batch[0].len(), 3);
+    assert_eq!(batch[0].as_slice()[0].detector_confidence, sigmoid(0.9));
+
+    // Wrong number of classes gives an error.
+    assert!(parse_predictions(
+        preds.view().into_dyn(),
+        &transforms,
+        80,
+        false,
+        0.25,
+        &mut FrameTimes::default()
+    )
+    .is_err());
+}
@@ -1,48 +1,117 @@
 use std::time::Instant;
 
 use gstreamed_common::{
-    bbox::{non_maximum_suppression, Bbox},
+    bbox::{non_maximum_suppression, Bbox, Detections},
     frame_times::FrameTimes,
     img_dimensions::ImgDimensions,
+    letterbox::LetterboxTransform,
 };
 use ndarray::{s, ArrayView, Axis, Dim, IxDyn};
 
-/// Parse yolov8 predictions via `ort`.
+use crate::output_layout::OutputLayout;
+
+/// Parse yolo predictions via `ort`, for a batch of images.
+///
+/// `preds` are of shape `[bsz, d1, d2]` in the given `layout`, with bboxes in the letterboxed coordinates
+/// of each image, which are mapped back to the original image with its [LetterboxTransform] in `transforms`.
+/// Batch elements beyond `transforms`, which pad the batch to a fixed model batch size, are ignored.
+///
+/// Predictions of [OutputLayout::EndToEnd] models are final detections, so they skip NMS.
+///
+/// Returns [Detections] of each image, in the order of `transforms`.
 pub fn parse_predictions(
     preds: ArrayView<f32, IxDyn>,
-    scaled_dims: ImgDimensions,
+    layout: OutputLayout,
+    transforms: &[LetterboxTransform],
     num_clases: u32,
     conf_threshold: f32,
     nms_threshold: f32,
     frame_times: &mut FrameTimes,
-) -> anyhow::Result<Vec<Vec<Bbox>>> {
-    // preds.shape: [bsz, embedding, anchors]
+) -> anyhow::Result<Vec<Detections>> {
+    // preds.shape: [bsz, embedding, anchors] for v8 layout
     // [1, 84, 5040]
-    // TODO batch support with another loop outside
+    log::debug!("preds.shape: {:?}", preds.shape());
+    layout.check(preds.shape(), num_clases as usize)?;
+    anyhow::ensure!(
+        preds.shape()[0] >= transforms.len(),
+        "Expected predictions of {} images, got: {:?}",
+        transforms.len(),
+        preds.shape()
+    );
+
+    let mut batch = Vec::with_capacity(transforms.len());
+    for (index, transform) in transforms.iter().enumerate() {
+        // Need to specify full dimensions here so rust can infer slices correctly later.
+        let image_preds: ArrayView<f32, Dim<[usize; 2]>> = preds.slice(s![index, .., ..]);
+        let mut detections = match layout {
+            OutputLayout::EndToEnd => parse_end_to_end_predictions(
+                image_preds,
+                transform.letterboxed_dims,
+                num_clases,
+                conf_threshold,
+                frame_times,
+            )?,
+            // Transposing the view is free, so all other layouts are parsed as [anchors, embedding].
+            _ => parse_image_predictions(
+                match layout {
+                    OutputLayout::V8 => image_preds.reversed_axes(),
+                    _ => image_preds,
+                },
+                layout == OutputLayout::V5,
+                transform.letterboxed_dims,
+                num_clases,
+                conf_threshold,
+                nms_threshold,
+                frame_times,
+            ),
+        };
+        log::debug!("after nms bboxes, len: {:?}", detections.len());
+
+        // Map bboxes back to the original image coordinates.
+        transform.to_original(&mut detections);
+        batch.push(detections);
+    }
 
+    Ok(batch)
+}
+
+/// Parses predictions of a single image, of shape `[anchors, 4 + classes]`, into bboxes in letterboxed coordinates.
+/// With `objectness`, predictions are `[anchors, 5 + classes]`, and class scores are scaled by the objectness.
+///
+/// Stage times are added to `frame_times`.
+fn parse_image_predictions(
+    preds: ArrayView<f32, Dim<[usize; 2]>>,
+    objectness: bool,
+    letterboxed_dims: ImgDimensions,
+    num_clases: u32,
+    conf_threshold: f32,
+    nms_threshold: f32,
+    frame_times: &mut FrameTimes,
+) -> Detections {
     let start = Instant::now();
-    log::debug!("preds.shape: {:?}", preds.shape());
-    // Get rid of the first axis.
-    // Need to specify full dimensions here so rust can infer slices correctly later.
-    let preds: ArrayView<f32, Dim<[usize; 2]>> = preds.slice(s![0, .., ..]);
-    // Gives us a shape of [84, 5040].
+    // Gives us a shape of [5040, 84].
     log::debug!("preds2.shape: {:?}", preds.shape());
 
-    let mut bboxes_per_class: Vec<Vec<Bbox>> = vec![Vec::new(); num_clases as usize];
-    for pred in preds.axis_iter(Axis(1)) {
+    let mut detections = Detections::new(letterboxed_dims);
+    for pred in preds.axis_iter(Axis(0)) {
         log::trace!("pred.shape: {:?}", pred.shape());
         // Separate bbox and class values.
-        // First 4 values correspond to bbox cx, cy, w, h
+        // First 4 values correspond to bbox cx, cy, w, h, followed by objectness, if any.
         const BBOX_OFFSET: usize = 4;
         let bbox = pred.slice(s![0..BBOX_OFFSET]);
-        let clss = pred.slice(s![BBOX_OFFSET..BBOX_OFFSET + num_clases as usize]);
+        let (class_offset, object_confidence) = match objectness {
+            true => (BBOX_OFFSET + 1, pred[BBOX_OFFSET]),
+            false => (BBOX_OFFSET, 1.0),
+        };
+        let clss = pred.slice(s![class_offset..class_offset + num_clases as usize]);
 
         // Determine top1 class and its confidence.
         let mut max_class_id = 0;
         let mut max_confidence = 0f32;
         for (idx, cls_conf) in clss.into_iter().enumerate() {
-            if cls_conf > &max_confidence {
-                max_confidence = *cls_conf;
+            let cls_conf = cls_conf * object_confidence;
+            if cls_conf > max_confidence {
+                max_confidence = cls_conf;
                 max_class_id = idx;
             }
         }
@@ -64,31 +133,249 @@ pub fn parse_predictions(
         let xmax = xmin + w;
         let ymax = ymin + h;
 
-        // Bound coords to scaled dimensions, so bboxes don't go outside the image.
+        // Bound coords to letterboxed dimensions, so bboxes don't go outside the image.
         let y_bbox = Bbox {
-            xmin: xmin.max(0.0f32).min(scaled_dims.width),
-            ymin: ymin.max(0.0f32).min(scaled_dims.height),
-            xmax: xmax.max(0.0f32).min(scaled_dims.width),
-            ymax: ymax.max(0.0f32).min(scaled_dims.height),
+            xmin: xmin.max(0.0f32).min(letterboxed_dims.width),
+            ymin: ymin.max(0.0f32).min(letterboxed_dims.height),
+            xmax: xmax.max(0.0f32).min(letterboxed_dims.width),
+            ymax: ymax.max(0.0f32).min(letterboxed_dims.height),
             detector_confidence: max_confidence,
             tracker_confidence: 0f32,
             data: vec![],
             class: max_class_id,
             tracker_id: None,
+            observed: None,
+            predicted: false,
         };
 
-        bboxes_per_class[max_class_id].push(y_bbox);
+        detections.push(y_bbox);
     }
-    frame_times.bbox_extraction = start.elapsed();
+    frame_times.bbox_extraction += start.elapsed();
 
     // nms
     let start = Instant::now();
-    log::debug!(
-        "be4 nms bboxes, len: {:?}",
-        bboxes_per_class.iter().map(|v| v.len()).sum::<usize>()
+    log::debug!("be4 nms bboxes, len: {:?}", detections.len());
+    non_maximum_suppression(&mut detections, nms_threshold);
+    frame_times.nms += start.elapsed();
+
+    detections
+}
+
+/// Parses final detections of an end-to-end model for a single image, of shape `[max_det, 6]`,
+/// into bboxes in letterboxed coordinates.
+///
+/// Each detection is `x1, y1, x2, y2, score, class`, with unused slots zeroed,
+/// so they fall below `conf_threshold`. Stage times are added to `frame_times`.
+fn parse_end_to_end_predictions(
+    preds: ArrayView<f32, Dim<[usize; 2]>>,
+    letterboxed_dims: ImgDimensions,
+    num_clases: u32,
+    conf_threshold: f32,
+    frame_times: &mut FrameTimes,
+) -> anyhow::Result<Detections> {
+    let start = Instant::now();
+    log::debug!("preds2.shape: {:?}", preds.shape());
+
+    let mut detections = Detections::new(letterboxed_dims);
+    for pred in preds.axis_iter(Axis(0)) {
+        let confidence = pred[4];
+        if confidence < conf_threshold {
+            continue;
+        }
+        let class = pred[5].round() as usize;
+        anyhow::ensure!(
+            class < num_clases as usize,
+            "Model predicted class {class}, but only {num_clases} labels were given"
+        );
+
+        // Bound coords to letterboxed dimensions, so bboxes don't go outside the image.
+        let bbox = Bbox {
+            xmin: pred[0].max(0.0f32).min(letterboxed_dims.width),
+            ymin: pred[1].max(0.0f32).min(letterboxed_dims.height),
+            xmax: pred[2].max(0.0f32).min(letterboxed_dims.width),
+            ymax: pred[3].max(0.0f32).min(letterboxed_dims.height),
+            detector_confidence: confidence,
+            tracker_confidence: 0f32,
+            data: vec![],
+            class,
+            tracker_id: None,
+            observed: None,
+            predicted: false,
+        };
+        detections.push(bbox);
+    }
+    frame_times.bbox_extraction += start.elapsed();
+
+    Ok(detections)
+}
+
+#[test]
+fn parse_predictions_maps_each_image_of_batch() {
+    use ndarray::Array3;
+
+    // 2 images of different sizes, both letterboxed into 640x640, plus batch padding.
+    let target = ImgDimensions::new(640.0, 640.0);
+    let transforms = [
+        LetterboxTransform::new(ImgDimensions::new(1280.0, 720.0), target, None),
+        LetterboxTransform::new(ImgDimensions::new(640.0, 640.0), target, None),
+    ];
+    // [bsz, 4 + 2 classes, 2 anchors]
+    let mut preds = Array3::<f32>::zeros((3, 6, 2));
+    // Image 0: class 1 bbox centered at (320, 320), 100x50.
+    for (row, value) in [320.0, 320.0, 100.0, 50.0, 0.1, 0.9]
+        .into_iter()
+        .enumerate()
+    {
+        preds[[0, row, 0]] = value;
+    }
+    // Image 1: class 0 bbox at the same place, and a low confidence one.
+    for (row, value) in [320.0, 320.0, 100.0, 50.0, 0.8, 0.0]
+        .into_iter()
+        .enumerate()
+    {
+        preds[[1, row, 1]] = value;
+        preds[[1, row, 0]] = value * 0.1;
+    }
+    // Padding, which must be ignored.
+    preds[[2, 4, 0]] = 1.0;
+
+    let batch = parse_predictions(
+        preds.view().into_dyn(),
+        OutputLayout::V8,
+        &transforms,
+        2,
+        0.25,
+        0.45,
+        &mut FrameTimes::default(),
+    )
+    .unwrap();
+    assert_eq!(batch.len(), 2);
+
+    let [bbox] = batch[0].as_slice() else {
+        panic!("Expected a single bbox, got {:?}", batch[0]);
+    };
+    assert_eq!(batch[0].frame_dims, ImgDimensions::new(1280.0, 720.0));
+    assert_eq!(bbox.class, 1);
+    // Scaled by 2 and shifted by 140 px vertical padding.
+    assert_eq!(
+        (bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax),
+        (540.0, 310.0, 740.0, 410.0)
+    );
+
+    let [bbox] = batch[1].as_slice() else {
+        panic!("Expected a single bbox, got {:?}", batch[1]);
+    };
+    assert_eq!(bbox.class, 0);
+    assert_eq!(
+        (bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax),
+        (270.0, 295.0, 370.0, 345.0)
+    );
+}
+
+#[test]
+fn parse_predictions_reads_anchors_first_layouts() {
+    use ndarray::Array3;
+
+    let dims = ImgDimensions::new(640.0, 640.0);
+    let transforms = [LetterboxTransform::new(dims, dims, None)];
+    let parse = |preds: Array3<f32>, layout, num_classes| {
+        parse_predictions(
+            preds.view().into_dyn(),
+            layout,
+            &transforms,
+            num_classes,
+            0.25,
+            0.45,
+            &mut FrameTimes::default(),
+        )
+        .unwrap()
+        .remove(0)
+    };
+
+    // [bsz, 2 anchors, 4 + 2 classes], the 2nd anchor has class 1 bbox.
+    let mut preds = Array3::<f32>::zeros((1, 2, 6));
+    for (col, value) in [320.0, 320.0, 100.0, 50.0, 0.1, 0.9]
+        .into_iter()
+        .enumerate()
+    {
+        preds[[0, 1, col]] = value;
+    }
+    let detections = parse(preds, OutputLayout::V8Transposed, 2);
+    let [bbox] = detections.as_slice() else {
+        panic!("Expected a single bbox, got {detections:?}");
+    };
+    assert_eq!(bbox.class, 1);
+    assert_eq!(
+        (bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax),
+        (270.0, 295.0, 370.0, 345.0)
+    );
+
+    // [bsz, 2 anchors, 5 + 1 class], class scores are scaled by objectness.
+    let mut preds = Array3::<f32>::zeros((1, 2, 6));
+    for (col, value) in [320.0, 320.0, 100.0, 50.0, 0.2, 0.9]
+        .into_iter()
+        .enumerate()
+    {
+        preds[[0, 0, col]] = value;
+    }
+    for (col, value) in [100.0, 100.0, 40.0, 40.0, 0.8, 0.9].into_iter().enumerate() {
+        preds[[0, 1, col]] = value;
+    }
+    let detections = parse(preds, OutputLayout::V5, 1);
+    let [bbox] = detections.as_slice() else {
+        panic!("Expected a single bbox, got {detections:?}");
+    };
+    assert_eq!((bbox.class, bbox.xmin, bbox.ymin), (0, 80.0, 80.0));
+    assert!((bbox.detector_confidence - 0.72).abs() < 1e-6);
+}
+
+#[test]
+fn parse_predictions_keeps_end_to_end_detections() {
+    use ndarray::Array3;
+
+    let target = ImgDimensions::new(640.0, 640.0);
+    let transforms = [LetterboxTransform::new(
+        ImgDimensions::new(1280.0, 720.0),
+        target,
+        None,
+    )];
+    // [bsz, max_det, 6], 2 overlapping bboxes, which are kept without NMS, and an empty slot.
+    let mut preds = Array3::<f32>::zeros((1, 3, 6));
+    for (det, values) in [
+        [270.0, 295.0, 370.0, 345.0, 0.9, 1.0],
+        [275.0, 295.0, 375.0, 345.0, 0.8, 1.0],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        for (col, value) in values.into_iter().enumerate() {
+            preds[[0, det, col]] = value;
+        }
+    }
+
+    let parse = |num_classes| {
+        parse_predictions(
+            preds.view().into_dyn(),
+            OutputLayout::EndToEnd,
+            &transforms,
+            num_classes,
+            0.25,
+            0.45,
+            &mut FrameTimes::default(),
+        )
+    };
+    let batch = parse(2).unwrap();
+    let [first, second] = batch[0].as_slice() else {
+        panic!("Expected 2 bboxes, got {:?}", batch[0]);
+    };
+    assert_eq!((first.class, first.detector_confidence), (1, 0.9));
+    // Scaled by 2 and shifted by 140 px vertical padding.
+    assert_eq!(
+        (first.xmin, first.ymin, first.xmax, first.ymax),
+        (540.0, 310.0, 740.0, 410.0)
     );
-    non_maximum_suppression(&mut bboxes_per_class, nms_threshold);
-    frame_times.nms = start.elapsed();
+    assert_eq!(second.detector_confidence, 0.8);
 
-    Ok(bboxes_per_class)
+    // Classes beyond the labels give an error.
+    assert!(parse(1).is_err());
 }
@@ -0,0 +1,312 @@
+//! ByteTrack multi-object tracker, see <https://arxiv.org/abs/2110.06864>.
+//!
+//! Unlike SORT, low confidence detections are not thrown away, but associated with the
+//! remaining tracks in a second stage, which keeps tracks alive through partial occlusions.
+//! Tracks that lose their detections are kept in a lost track buffer for
+//! [TrackerConfig::max_idle_epochs] epochs, so they can be recovered with the same id.
+
+use std::time::Duration;
+
+use gstreamed_common::bbox::{iou, Bbox, Detections};
+use gstreamed_common::img_dimensions::ImgDimensions;
+use serde::Deserialize;
+
+use crate::clock::{whole_epochs, FrameClock};
+use crate::kalman::{KalmanFilter, KalmanState};
+use crate::{assign, observed, predicted, Tracker, TrackerConfig, INVALID_COST};
+
+/// ByteTrack specific parameters, the rest are shared with SORT in [TrackerConfig].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ByteTrackConfig {
+    /// Detections with at least this confidence are associated in the first stage.
+    pub high_threshold: f32,
+    /// Detections with confidence in `[low_threshold, high_threshold)` are associated
+    /// in the second stage, less confident ones are dropped.
+    pub low_threshold: f32,
+    /// Minimal confidence of an unmatched detection to start a new track.
+    pub new_track_threshold: f32,
+    /// Minimal IoU for the second stage association with low confidence detections.
+    pub low_iou_threshold: f32,
+}
+
+impl Default for ByteTrackConfig {
+    fn default() -> Self {
+        Self {
+            high_threshold: 0.5,
+            low_threshold: 0.1,
+            new_track_threshold: 0.6,
+            low_iou_threshold: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TrackState {
+    Tracked,
+    Lost,
+}
+
+#[derive(Debug, Clone)]
+struct Track {
+    id: i64,
+    kalman: KalmanState,
+    state: TrackState,
+    /// Whether the track was confirmed by a second observation (or started on the first frame).
+    activated: bool,
+    /// Epoch the track was last observed on.
+    last_epoch: u64,
+    /// Last observed detection.
+    detection: Bbox,
+}
+
+impl Track {
+    /// Current Kalman state of the track as a bbox, with class and confidence of the last detection.
+    ///
+    /// Falls back to the last detection, if the state has degenerated into an invalid bbox.
+    fn bbox(&self) -> Bbox {
+        let [xmin, ymin, xmax, ymax] = self
+            .kalman
+            .ltrb()
+            .unwrap_or_else(|| observed(&self.detection));
+        Bbox {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+            tracker_confidence: self.detection.detector_confidence,
+            tracker_id: Some(self.id),
+            observed: Some(observed(&self.detection)),
+            ..self.detection.clone()
+        }
+    }
+}
+
+/// ByteTrack tracker, see module docs.
+///
+/// Motion is predicted over the time elapsed between frames, see [FrameClock].
+pub struct ByteTrack {
+    config: TrackerConfig,
+    filter: KalmanFilter,
+    clock: FrameClock,
+    /// Tracked (confirmed or not) and lost tracks.
+    tracks: Vec<Track>,
+    /// Epochs elapsed since the start, counting the current frame.
+    epoch: u64,
+    next_id: i64,
+}
+
+impl ByteTrack {
+    pub fn new(config: &TrackerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            filter: KalmanFilter::new(config.position_weight, config.velocity_weight),
+            clock: FrameClock::new(config.frame_rate),
+            tracks: Vec::new(),
+            epoch: 0,
+            next_id: 1,
+        }
+    }
+
+    /// Advances to the frame at `timestamp`, predicting motion of all tracks.
+    fn advance(&mut self, timestamp: Option<Duration>) {
+        let elapsed = self.clock.tick(timestamp);
+        self.epoch += whole_epochs(elapsed);
+        for track in &mut self.tracks {
+            track.kalman = self.filter.predict(&track.kalman, elapsed);
+        }
+    }
+
+    /// Matches `tracks` (indices into `self.tracks`) with `detections`.
+    ///
+    /// Returns matched `(track, detection)` pairs, unmatched tracks and unmatched detection indices.
+    /// If `fuse_score` is set, IoU is weighted by detection confidence.
+    fn associate(
+        &self,
+        tracks: &[usize],
+        detections: &[&Bbox],
+        iou_threshold: f32,
+        fuse_score: bool,
+    ) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
+        let predicted: Vec<_> = tracks.iter().map(|&t| self.tracks[t].bbox()).collect();
+        let cost: Vec<Vec<f64>> = predicted
+            .iter()
+            .map(|track| {
+                detections
+                    .iter()
+                    .map(|det| {
+                        let weight = if fuse_score {
+                            det.detector_confidence
+                        } else {
+                            1.0
+                        };
+                        let similarity = iou(track, det) * weight;
+                        if similarity >= iou_threshold {
+                            1.0 - similarity as f64
+                        } else {
+                            INVALID_COST
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let matches = assign(&cost);
+        let unmatched_tracks = (0..tracks.len())
+            .filter(|t| !matches.iter().any(|(mt, _)| mt == t))
+            .map(|t| tracks[t])
+            .collect();
+        let unmatched_detections = (0..detections.len())
+            .filter(|d| !matches.iter().any(|(_, md)| md == d))
+            .collect();
+        let matches = matches.into_iter().map(|(t, d)| (tracks[t], d)).collect();
+        (matches, unmatched_tracks, unmatched_detections)
+    }
+
+    /// Updates the track with index `t` with the matched `detection`.
+    fn update_track(&mut self, t: usize, detection: &Bbox) {
+        let track = &mut self.tracks[t];
+        track.kalman = self.filter.update(&track.kalman, observed(detection));
+        track.state = TrackState::Tracked;
+        track.activated = true;
+        track.last_epoch = self.epoch;
+        track.detection = detection.clone();
+    }
+}
+
+impl Tracker for ByteTrack {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        let first = self.epoch == 0;
+        self.advance(timestamp);
+        let byte_config = &self.config.byte_track;
+        let high: Vec<_> = detections
+            .iter()
+            .filter(|det| det.detector_confidence >= byte_config.high_threshold)
+            .collect();
+        let low: Vec<_> = detections
+            .iter()
+            .filter(|det| {
+                (byte_config.low_threshold..byte_config.high_threshold)
+                    .contains(&det.detector_confidence)
+            })
+            .collect();
+        let (low_iou_threshold, new_track_threshold) = (
+            byte_config.low_iou_threshold,
+            byte_config.new_track_threshold,
+        );
+
+        let (confirmed, unconfirmed): (Vec<usize>, Vec<usize>) =
+            (0..self.tracks.len()).partition(|&t| self.tracks[t].activated);
+
+        // First stage: confirmed and lost tracks with confident detections.
+        let (matches, unmatched_tracks, unmatched_high) =
+            self.associate(&confirmed, &high, self.config.iou_threshold, true);
+        for (t, d) in matches {
+            self.update_track(t, high[d]);
+        }
+
+        // Second stage: remaining tracked (not lost) tracks with low confidence detections.
+        let remaining: Vec<_> = unmatched_tracks
+            .into_iter()
+            .filter(|&t| self.tracks[t].state == TrackState::Tracked)
+            .collect();
+        let (matches, unmatched_tracks, _) =
+            self.associate(&remaining, &low, low_iou_threshold, false);
+        for (t, d) in matches {
+            self.update_track(t, low[d]);
+        }
+        for t in unmatched_tracks {
+            self.tracks[t].state = TrackState::Lost;
+        }
+
+        // Unconfirmed tracks, which were only seen once, get a chance with remaining confident detections.
+        let high: Vec<_> = unmatched_high.into_iter().map(|d| high[d]).collect();
+        let (matches, unmatched_unconfirmed, unmatched_high) =
+            self.associate(&unconfirmed, &high, self.config.iou_threshold, true);
+        for (t, d) in matches {
+            self.update_track(t, high[d]);
+        }
+        let mut removed = vec![false; self.tracks.len()];
+        for t in unmatched_unconfirmed {
+            removed[t] = true;
+        }
+        let mut removed = removed.into_iter();
+        self.tracks.retain(|_| !removed.next().unwrap());
+
+        // Lost tracks are removed after they've been idle for too long.
+        let (epoch, max_idle) = (self.epoch, self.config.max_idle_epochs as u64);
+        self.tracks
+            .retain(|track| epoch - track.last_epoch <= max_idle);
+
+        // Remaining confident detections start new tracks.
+        for d in unmatched_high {
+            let detection = high[d];
+            if detection.detector_confidence < new_track_threshold {
+                continue;
+            }
+            self.tracks.push(Track {
+                id: self.next_id,
+                kalman: self.filter.initiate(observed(detection)),
+                state: TrackState::Tracked,
+                // Only tracks started on the first frame are confirmed right away.
+                activated: first,
+                last_epoch: self.epoch,
+                detection: detection.clone(),
+            });
+            self.next_id += 1;
+        }
+
+        let tracked = self
+            .tracks
+            .iter()
+            .filter(|track| track.activated && track.last_epoch == self.epoch)
+            .map(|track| {
+                let mut bbox = track.bbox();
+                bbox.clamp(detections.frame_dims);
+                bbox
+            })
+            .collect();
+        detections.with_bboxes(tracked)
+    }
+
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.advance(timestamp);
+        // Tracks matched on the latest update are still tracked, the rest are lost or unconfirmed.
+        let predictions = self
+            .tracks
+            .iter()
+            .filter(|track| track.activated && track.state == TrackState::Tracked)
+            .map(|track| predicted(track.bbox(), frame_dims))
+            .collect();
+        Detections::from_bboxes(predictions, frame_dims)
+    }
+}
+
+#[test]
+fn byte_track_keeps_ids_through_low_confidence_and_occlusion() {
+    use crate::{test_detections, test_track};
+
+    let mut tracker = ByteTrack::new(&TrackerConfig::default());
+    // The object at `x`, detected with `confidence`, if at all, and its expected track.
+    let frames = [
+        (100.0, Some(0.9), Some(1)),
+        (102.0, Some(0.9), Some(1)),
+        // Partially occluded object is still tracked, through the second stage.
+        (104.0, Some(0.3), Some(1)),
+        // Fully occluded object is lost, but not forgotten.
+        (106.0, None, None),
+        (108.0, None, None),
+        (110.0, Some(0.9), Some(1)),
+        // Too low confidence detections are dropped.
+        (112.0, Some(0.05), None),
+    ];
+    for (x, confidence, id) in frames {
+        let mut detections = test_detections(&[(x, 0)]);
+        detections.retain(|_| confidence.is_some());
+        for bbox in detections.iter_mut() {
+            bbox.detector_confidence = confidence.unwrap();
+        }
+        let tracked = test_track(&mut tracker, &detections, &[]);
+        assert_eq!(tracked, Vec::from_iter(id.map(|id| (0, id))), "at {x}");
+    }
+}
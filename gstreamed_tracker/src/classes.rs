@@ -0,0 +1,270 @@
+//! Class handling on top of the (class agnostic) trackers, see [ClassMode].
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use gstreamed_common::bbox::Detections;
+use gstreamed_common::img_dimensions::ImgDimensions;
+use serde::Deserialize;
+
+use crate::clock::FrameClock;
+use crate::{Tracker, TrackerConfig};
+
+/// How detection classes are handled by the tracker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassMode {
+    /// Association is purely positional, tracks take the class of the latest detection.
+    #[default]
+    Agnostic,
+    /// Tracks are only ever matched with detections of the same class,
+    /// each class is tracked by a separate tracker.
+    Strict,
+    /// Association is positional, but tracks take the majority class of their history,
+    /// which smooths out detector class flicker.
+    Vote,
+}
+
+/// Tracks each class with its own tracker, so tracks never switch class.
+///
+/// Track ids are remapped, so they stay unique across classes.
+/// Ids are forgotten after [TrackerConfig::max_idle_epochs] epochs, measured with a [FrameClock]
+/// like the class trackers do.
+pub struct PerClassTracker {
+    config: TrackerConfig,
+    create: fn(&TrackerConfig) -> Box<dyn Tracker>,
+    trackers: BTreeMap<usize, Box<dyn Tracker>>,
+    /// Global track id and last epoch seen of each `(class, class tracker id)`.
+    ids: HashMap<(usize, i64), (i64, u64)>,
+    next_id: i64,
+    clock: FrameClock,
+    /// Epochs elapsed since the start.
+    epoch: u64,
+}
+
+impl PerClassTracker {
+    /// Creates per class trackers with `create`, configured by `config`, as classes are encountered.
+    pub fn new(config: &TrackerConfig, create: fn(&TrackerConfig) -> Box<dyn Tracker>) -> Self {
+        Self {
+            config: config.clone(),
+            create,
+            trackers: BTreeMap::new(),
+            ids: HashMap::new(),
+            next_id: 1,
+            clock: FrameClock::new(config.frame_rate),
+            epoch: 0,
+        }
+    }
+}
+
+impl Tracker for PerClassTracker {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        self.update_with_features(detections, &[], timestamp)
+    }
+
+    fn update_with_features(
+        &mut self,
+        detections: &Detections,
+        features: &[Vec<f32>],
+        timestamp: Option<Duration>,
+    ) -> Detections {
+        self.epoch += self.clock.tick_epochs(timestamp);
+        for class in detections.classes() {
+            if !self.trackers.contains_key(&class) {
+                self.trackers.insert(class, (self.create)(&self.config));
+            }
+        }
+
+        let mut tracked = Vec::with_capacity(detections.len());
+        // Every class tracker is updated, even without detections, so its tracks age.
+        for (&class, tracker) in &mut self.trackers {
+            let (class_detections, class_features): (Vec<_>, Vec<_>) = detections
+                .iter()
+                .enumerate()
+                .filter(|(_, bbox)| bbox.class == class)
+                .map(|(i, bbox)| (bbox.clone(), features.get(i).cloned().unwrap_or_default()))
+                .unzip();
+            let class_detections = detections.with_bboxes(class_detections);
+            let class_tracked = if features.is_empty() {
+                tracker.update(&class_detections, timestamp)
+            } else {
+                tracker.update_with_features(&class_detections, &class_features, timestamp)
+            };
+
+            for mut bbox in class_tracked {
+                if let Some(id) = bbox.tracker_id {
+                    let (global_id, last_epoch) =
+                        self.ids.entry((class, id)).or_insert_with(|| {
+                            self.next_id += 1;
+                            (self.next_id - 1, self.epoch)
+                        });
+                    *last_epoch = self.epoch;
+                    bbox.tracker_id = Some(*global_id);
+                }
+                tracked.push(bbox);
+            }
+        }
+
+        // Class trackers forget idle tracks, so we can forget their ids as well.
+        let (epoch, max_idle) = (self.epoch, self.config.max_idle_epochs as u64);
+        self.ids
+            .retain(|_, (_, last_epoch)| epoch - *last_epoch <= max_idle);
+        detections.with_bboxes(tracked)
+    }
+
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.epoch += self.clock.tick_epochs(timestamp);
+        let mut predictions = Vec::new();
+        for (&class, tracker) in &mut self.trackers {
+            for mut bbox in tracker.predict(frame_dims, timestamp) {
+                // Predicted tracks were seen on the latest update, so their ids are known.
+                let Some(&(global_id, _)) =
+                    bbox.tracker_id.and_then(|id| self.ids.get(&(class, id)))
+                else {
+                    continue;
+                };
+                bbox.tracker_id = Some(global_id);
+                predictions.push(bbox);
+            }
+        }
+        Detections::from_bboxes(predictions, frame_dims)
+    }
+
+    fn finish(&mut self) {
+        for tracker in self.trackers.values_mut() {
+            tracker.finish();
+        }
+    }
+}
+
+/// Class votes of a single track.
+struct Votes {
+    counts: BTreeMap<usize, u32>,
+    /// Class won by the latest vote.
+    class: usize,
+    last_epoch: u64,
+}
+
+/// Smooths classes of tracks by majority vote over their history.
+pub struct ClassVoteTracker {
+    tracker: Box<dyn Tracker>,
+    max_idle_epochs: u64,
+    votes: HashMap<i64, Votes>,
+    clock: FrameClock,
+    /// Epochs elapsed since the start.
+    epoch: u64,
+}
+
+impl ClassVoteTracker {
+    /// Wraps the `tracker` created with `config`, forgetting votes of tracks idle for more than
+    /// [TrackerConfig::max_idle_epochs], measured with a [FrameClock] like the wrapped tracker does.
+    pub fn new(tracker: Box<dyn Tracker>, config: &TrackerConfig) -> Self {
+        Self {
+            tracker,
+            max_idle_epochs: config.max_idle_epochs as u64,
+            votes: HashMap::new(),
+            clock: FrameClock::new(config.frame_rate),
+            epoch: 0,
+        }
+    }
+}
+
+impl Tracker for ClassVoteTracker {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        self.update_with_features(detections, &[], timestamp)
+    }
+
+    fn update_with_features(
+        &mut self,
+        detections: &Detections,
+        features: &[Vec<f32>],
+        timestamp: Option<Duration>,
+    ) -> Detections {
+        self.epoch += self.clock.tick_epochs(timestamp);
+        let mut tracked = if features.is_empty() {
+            self.tracker.update(detections, timestamp)
+        } else {
+            self.tracker
+                .update_with_features(detections, features, timestamp)
+        };
+
+        for bbox in tracked.iter_mut() {
+            let Some(id) = bbox.tracker_id else {
+                continue;
+            };
+            let votes = self.votes.entry(id).or_insert_with(|| Votes {
+                counts: BTreeMap::new(),
+                class: bbox.class,
+                last_epoch: self.epoch,
+            });
+            votes.last_epoch = self.epoch;
+            *votes.counts.entry(bbox.class).or_default() += 1;
+            // Ties are won by the current class, so the class doesn't flip back and forth.
+            let current = votes.counts[&bbox.class];
+            if let Some((&class, _)) = votes
+                .counts
+                .iter()
+                .filter(|(_, &count)| count > current)
+                .max_by_key(|(_, &count)| count)
+            {
+                bbox.class = class;
+            }
+            votes.class = bbox.class;
+        }
+
+        let (epoch, max_idle) = (self.epoch, self.max_idle_epochs);
+        self.votes
+            .retain(|_, votes| epoch - votes.last_epoch <= max_idle);
+        tracked
+    }
+
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.epoch += self.clock.tick_epochs(timestamp);
+        let mut predictions = self.tracker.predict(frame_dims, timestamp);
+        // Predictions don't vote, but get the class voted so far.
+        for bbox in predictions.iter_mut() {
+            if let Some(votes) = bbox.tracker_id.and_then(|id| self.votes.get(&id)) {
+                bbox.class = votes.class;
+            }
+        }
+        predictions
+    }
+
+    fn finish(&mut self) {
+        self.tracker.finish();
+    }
+}
+
+#[test]
+fn strict_tracks_never_switch_class() {
+    use crate::{test_detections, test_track};
+
+    let config = TrackerConfig {
+        kind: crate::TrackerKind::Iou,
+        ..Default::default()
+    };
+    let mut tracker = PerClassTracker::new(&config, crate::class_agnostic_tracker);
+
+    let detections = test_detections(&[(100.0, 0), (300.0, 1)]);
+    assert_eq!(test_track(&mut tracker, &detections, &[]), [(0, 1), (1, 2)]);
+    // Same place, but different class, so it's a different track.
+    let detections = test_detections(&[(102.0, 2), (302.0, 1)]);
+    assert_eq!(test_track(&mut tracker, &detections, &[]), [(1, 2), (2, 3)]);
+    let detections = test_detections(&[(104.0, 0)]);
+    assert_eq!(test_track(&mut tracker, &detections, &[]), [(0, 1)]);
+}
+
+#[test]
+fn vote_smooths_class_flicker() {
+    use crate::{test_detections, test_track};
+
+    let config = TrackerConfig::default();
+    let tracker = Box::new(crate::IouTracker::new(&config));
+    let mut tracker = ClassVoteTracker::new(tracker, &config);
+
+    for (class, voted) in [(0, 0), (0, 0), (5, 0), (5, 5), (5, 5)] {
+        let detections = test_detections(&[(100.0, class)]);
+        assert_eq!(test_track(&mut tracker, &detections, &[]), [(voted, 1)]);
+    }
+}
@@ -0,0 +1,85 @@
+//! Conversion of frame timestamps into tracker epochs.
+//!
+//! Trackers count time in epochs, nominally frames. With variable frame rate, dropped frames,
+//! or detection on a subset of frames, the time between updates varies, so each update is
+//! measured in epochs of the nominal frame interval instead.
+
+use std::time::Duration;
+
+/// Shorter intervals between timestamps are considered glitches and don't affect the estimate.
+const MIN_FRAME_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Measures elapsed epochs between tracker updates, see module docs.
+#[derive(Debug, Clone)]
+pub struct FrameClock {
+    /// Nominal duration of an epoch, if known.
+    frame_interval: Option<Duration>,
+    /// Whether `frame_interval` was configured, instead of estimated from timestamps.
+    fixed: bool,
+    last_timestamp: Option<Duration>,
+}
+
+impl FrameClock {
+    /// Creates a clock with epochs of `1 / frame_rate`, if given,
+    /// otherwise epochs are the shortest interval between timestamps seen so far.
+    pub fn new(frame_rate: Option<f32>) -> Self {
+        let frame_interval = frame_rate.map(|fps| Duration::from_secs_f32(1.0 / fps));
+        Self {
+            frame_interval,
+            fixed: frame_interval.is_some(),
+            last_timestamp: None,
+        }
+    }
+
+    /// Returns epochs elapsed since the previous update, at `timestamp`.
+    ///
+    /// Updates without timestamps, or with timestamps not after the previous one, are 1 epoch apart.
+    pub fn tick(&mut self, timestamp: Option<Duration>) -> f32 {
+        let Some(timestamp) = timestamp else {
+            return 1.0;
+        };
+        let last_timestamp = self.last_timestamp.replace(timestamp);
+        let elapsed = match last_timestamp {
+            Some(last) if timestamp > last => timestamp - last,
+            _ => return 1.0,
+        };
+        if !self.fixed && elapsed >= MIN_FRAME_INTERVAL {
+            let shortest = self
+                .frame_interval
+                .map_or(elapsed, |interval| interval.min(elapsed));
+            self.frame_interval = Some(shortest);
+        }
+        match self.frame_interval {
+            Some(interval) => elapsed.as_secs_f32() / interval.as_secs_f32(),
+            None => 1.0,
+        }
+    }
+
+    /// Same as [FrameClock::tick], rounded with [whole_epochs].
+    pub fn tick_epochs(&mut self, timestamp: Option<Duration>) -> u64 {
+        whole_epochs(self.tick(timestamp))
+    }
+}
+
+/// Rounds `elapsed` epochs to whole ones, at least 1, for counting idle epochs.
+pub fn whole_epochs(elapsed: f32) -> u64 {
+    (elapsed.round() as u64).max(1)
+}
+
+#[test]
+fn clock_measures_dropped_frames() {
+    let ms = |ms| Some(Duration::from_millis(ms));
+    let mut clock = FrameClock::new(None);
+    assert_eq!(clock.tick(ms(0)), 1.0);
+    assert_eq!(clock.tick(ms(40)), 1.0);
+    // 2 frames dropped.
+    assert_eq!(clock.tick_epochs(ms(160)), 3);
+    assert_eq!(clock.tick(None), 1.0);
+    // Shorter interval (higher frame rate) becomes the epoch.
+    assert_eq!(clock.tick(ms(180)), 1.0);
+    assert_eq!(clock.tick(ms(220)), 2.0);
+
+    let mut clock = FrameClock::new(Some(25.0));
+    clock.tick(ms(0));
+    assert!((clock.tick(ms(20)) - 0.5).abs() < 1e-3);
+}
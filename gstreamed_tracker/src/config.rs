@@ -0,0 +1,162 @@
+//! Tracker parameters, which can be loaded from yaml/json config files.
+
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use similari::trackers::sort::metric::DEFAULT_MINIMAL_SORT_CONFIDENCE;
+use similari::trackers::sort::DEFAULT_SORT_IOU_THRESHOLD;
+
+use crate::byte_track::ByteTrackConfig;
+use crate::classes::ClassMode;
+use crate::visual_sort::VisualSortConfig;
+
+/// Tracking algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackerKind {
+    /// SORT, Kalman filter and IoU association.
+    #[default]
+    Sort,
+    /// IoU tracker, which matches detections with last observed bboxes, without motion model.
+    Iou,
+    /// Visual SORT, SORT with appearance features.
+    VisualSort,
+    /// ByteTrack, which also associates low confidence detections.
+    ByteTrack,
+    /// No tracking, detections are passed through as is.
+    None,
+}
+
+/// Tracker parameters.
+///
+/// Epochs are frame intervals, so windows such as `max_idle_epochs` need to be scaled with the frame rate:
+/// 10 epochs are a sixth of a second at 60 fps, but 2 seconds at 5 fps.
+/// Dropped frames, and frames without detection, still count as epochs, see [crate::clock].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrackerConfig {
+    /// Tracking algorithm.
+    pub kind: TrackerKind,
+    /// How detection classes are associated with tracks.
+    pub class_mode: ClassMode,
+    /// How many epochs a track is kept without observations before it is removed.
+    pub max_idle_epochs: usize,
+    /// Nominal frame rate, epochs are measured in frame intervals of it.
+    /// If not set, the shortest interval between frame timestamps is used.
+    pub frame_rate: Option<f32>,
+    /// Minimal IoU between a prediction and a detection for them to be matched.
+    pub iou_threshold: f32,
+    /// SORT weights IoU by detection confidence, at least this much.
+    pub min_confidence: f32,
+    /// Kalman filter position weight.
+    pub position_weight: f32,
+    /// Kalman filter velocity weight.
+    pub velocity_weight: f32,
+    /// ByteTrack specific parameters, ignored by other trackers.
+    pub byte_track: ByteTrackConfig,
+    /// Visual SORT specific parameters, ignored by other trackers.
+    pub visual_sort: VisualSortConfig,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        // Largely untuned.
+        Self {
+            kind: TrackerKind::Sort,
+            class_mode: ClassMode::Agnostic,
+            max_idle_epochs: 10,
+            frame_rate: None,
+            iou_threshold: DEFAULT_SORT_IOU_THRESHOLD,
+            min_confidence: DEFAULT_MINIMAL_SORT_CONFIDENCE,
+            position_weight: 1.0 / 20.0,
+            velocity_weight: 1.0 / 160.0,
+            byte_track: ByteTrackConfig::default(),
+            visual_sort: VisualSortConfig::default(),
+        }
+    }
+}
+
+impl TrackerConfig {
+    /// Parses yaml config, missing fields are set to their defaults.
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Parses json config, missing fields are set to their defaults.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Loads config from a `.yaml`/`.yml` or `.json` file.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config = match path.extension().and_then(|os_str| os_str.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            Some("json") => Self::from_json(&contents),
+            _ => anyhow::bail!("Unsupported tracker config extension, expected .yaml or .json"),
+        };
+        config
+            .and_then(|config| config.validate().map(|_| config))
+            .map_err(|err| err.context(format!("Failed to load tracker config from {path:?}")))
+    }
+
+    /// Checks that the parameters are usable.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.iou_threshold > 0.0 && self.iou_threshold <= 1.0,
+            "Tracker IoU threshold must be in (0, 1], got {}",
+            self.iou_threshold
+        );
+        anyhow::ensure!(
+            self.frame_rate.is_none_or(|fps| fps > 0.0),
+            "Tracker frame rate must be positive"
+        );
+        let byte_track = &self.byte_track;
+        anyhow::ensure!(
+            byte_track.low_threshold <= byte_track.high_threshold,
+            "ByteTrack low threshold {} must not exceed high threshold {}",
+            byte_track.low_threshold,
+            byte_track.high_threshold
+        );
+        let visual_sort = &self.visual_sort;
+        anyhow::ensure!(
+            (-1.0..=1.0).contains(&visual_sort.min_similarity),
+            "Visual SORT min similarity must be in [-1, 1], got {}",
+            visual_sort.min_similarity
+        );
+        anyhow::ensure!(
+            visual_sort.min_track_length > 0,
+            "Visual SORT min track length must be positive"
+        );
+        Ok(())
+    }
+}
+
+#[test]
+fn config_fills_in_defaults() {
+    let config = TrackerConfig::from_yaml("max_idle_epochs: 120\niou_threshold: 0.2\n").unwrap();
+    assert_eq!(
+        config,
+        TrackerConfig {
+            max_idle_epochs: 120,
+            iou_threshold: 0.2,
+            ..Default::default()
+        }
+    );
+    assert!(TrackerConfig::from_json(r#"{"max_idle": 5}"#).is_err());
+    // Empty config tracks like before configs were introduced, with SORT.
+    assert_eq!(
+        TrackerConfig::from_yaml("{}").unwrap().kind,
+        TrackerKind::Sort
+    );
+
+    let config =
+        TrackerConfig::from_yaml("kind: sort\nbyte_track:\n  low_threshold: 0.2\n").unwrap();
+    assert_eq!(config.kind, TrackerKind::Sort);
+    assert_eq!(config.byte_track.low_threshold, 0.2);
+    assert_eq!(config.byte_track.high_threshold, 0.5);
+
+    let config = TrackerConfig::from_yaml("class_mode: vote\n").unwrap();
+    assert_eq!(config.class_mode, ClassMode::Vote);
+}
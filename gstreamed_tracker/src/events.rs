@@ -0,0 +1,356 @@
+//! Track lifecycle events, so applications can follow objects appearing and disappearing,
+//! e.g. to count unique objects or to write a record per object, instead of per frame.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use gstreamed_common::bbox::{Bbox, Detections};
+use gstreamed_common::img_dimensions::ImgDimensions;
+use gstreamed_common::labels::LabelMap;
+use serde::Serialize;
+
+use crate::clock::FrameClock;
+use crate::{Tracker, TrackerConfig};
+
+/// What happened to a track.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackEventKind {
+    /// Track was seen for the first time.
+    Created,
+    /// Track was seen again, possibly after being lost.
+    Updated,
+    /// Track was not seen on this frame, but may still come back.
+    Lost,
+    /// Track was not seen for too long, or the stream ended, it won't come back.
+    Terminated,
+}
+
+/// A single observation of a track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryPoint {
+    /// 0-based index of the frame.
+    pub frame: u64,
+    /// Presentation timestamp of the frame, if known.
+    pub timestamp: Option<Duration>,
+    /// Tracked bbox.
+    pub bbox: Bbox,
+}
+
+/// Lifecycle event of a single track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackEvent {
+    pub kind: TrackEventKind,
+    pub track_id: i64,
+    /// Class of the latest observation.
+    pub class: usize,
+    pub first_frame: u64,
+    pub last_frame: u64,
+    pub first_timestamp: Option<Duration>,
+    pub last_timestamp: Option<Duration>,
+    /// Full trajectory for [TrackEventKind::Lost] and [TrackEventKind::Terminated] events,
+    /// just the latest point for [TrackEventKind::Created] and [TrackEventKind::Updated] ones,
+    /// so the trajectory isn't copied on every frame.
+    pub trajectory: Vec<TrajectoryPoint>,
+}
+
+struct TrackHistory {
+    trajectory: Vec<TrajectoryPoint>,
+    lost: bool,
+    /// Epoch of the latest observation.
+    last_epoch: u64,
+}
+
+impl TrackHistory {
+    fn event(&self, kind: TrackEventKind, track_id: i64, full: bool) -> TrackEvent {
+        let first = self.trajectory.first().unwrap();
+        let last = self.trajectory.last().unwrap();
+        TrackEvent {
+            kind,
+            track_id,
+            class: last.bbox.class,
+            first_frame: first.frame,
+            last_frame: last.frame,
+            first_timestamp: first.timestamp,
+            last_timestamp: last.timestamp,
+            trajectory: if full {
+                self.trajectory.clone()
+            } else {
+                vec![last.clone()]
+            },
+        }
+    }
+}
+
+/// Callback receiving track lifecycle events.
+pub type OnTrackEvent = Box<dyn FnMut(TrackEvent) + Send>;
+
+/// [Tracker] wrapper, which emits lifecycle events of the tracks of the wrapped tracker.
+///
+/// Tracks are terminated after [TrackerConfig::max_idle_epochs] epochs without observations,
+/// or when the stream ends with [Tracker::finish]. Epochs are measured with a [FrameClock],
+/// like the wrapped tracker does, so tracks are terminated when the wrapped tracker forgets them.
+pub struct LifecycleTracker {
+    tracker: Box<dyn Tracker>,
+    max_idle_epochs: u64,
+    clock: FrameClock,
+    on_event: OnTrackEvent,
+    tracks: BTreeMap<i64, TrackHistory>,
+    /// Index of the next frame.
+    frame: u64,
+    /// Epochs elapsed since the start.
+    epoch: u64,
+}
+
+impl LifecycleTracker {
+    /// Wraps the `tracker` created with `config`, calling `on_event` with each event.
+    pub fn new(tracker: Box<dyn Tracker>, config: &TrackerConfig, on_event: OnTrackEvent) -> Self {
+        Self {
+            tracker,
+            max_idle_epochs: config.max_idle_epochs as u64,
+            clock: FrameClock::new(config.frame_rate),
+            on_event,
+            tracks: BTreeMap::new(),
+            frame: 0,
+            epoch: 0,
+        }
+    }
+
+    /// Wraps the `tracker` created with `config`, sending events into the returned channel.
+    pub fn with_channel(
+        tracker: Box<dyn Tracker>,
+        config: &TrackerConfig,
+    ) -> (Self, Receiver<TrackEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let on_event = Box::new(move |event| {
+            // Nobody listening anymore is fine.
+            let _ = sender.send(event);
+        });
+        (Self::new(tracker, config, on_event), receiver)
+    }
+
+    fn emit(&mut self, kind: TrackEventKind, track_id: i64) {
+        let full = matches!(kind, TrackEventKind::Lost | TrackEventKind::Terminated);
+        let event = self.tracks[&track_id].event(kind, track_id, full);
+        (self.on_event)(event);
+    }
+}
+
+impl Tracker for LifecycleTracker {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        self.update_with_features(detections, &[], timestamp)
+    }
+
+    fn update_with_features(
+        &mut self,
+        detections: &Detections,
+        features: &[Vec<f32>],
+        timestamp: Option<Duration>,
+    ) -> Detections {
+        let tracked = if features.is_empty() {
+            self.tracker.update(detections, timestamp)
+        } else {
+            self.tracker
+                .update_with_features(detections, features, timestamp)
+        };
+        let frame = self.frame;
+        self.frame += 1;
+        self.epoch += self.clock.tick_epochs(timestamp);
+
+        for bbox in &tracked {
+            let Some(track_id) = bbox.tracker_id else {
+                continue;
+            };
+            let point = TrajectoryPoint {
+                frame,
+                timestamp,
+                bbox: bbox.clone(),
+            };
+            let kind = match self.tracks.get_mut(&track_id) {
+                Some(track) => {
+                    track.trajectory.push(point);
+                    track.lost = false;
+                    track.last_epoch = self.epoch;
+                    TrackEventKind::Updated
+                }
+                None => {
+                    let track = TrackHistory {
+                        trajectory: vec![point],
+                        lost: false,
+                        last_epoch: self.epoch,
+                    };
+                    self.tracks.insert(track_id, track);
+                    TrackEventKind::Created
+                }
+            };
+            self.emit(kind, track_id);
+        }
+
+        let mut lost = vec![];
+        let mut terminated = vec![];
+        for (&track_id, track) in &self.tracks {
+            if track.trajectory.last().unwrap().frame == frame {
+                continue;
+            }
+            if self.epoch - track.last_epoch > self.max_idle_epochs {
+                terminated.push(track_id);
+            } else if !track.lost {
+                lost.push(track_id);
+            }
+        }
+        for track_id in lost {
+            self.tracks.get_mut(&track_id).unwrap().lost = true;
+            self.emit(TrackEventKind::Lost, track_id);
+        }
+        for track_id in terminated {
+            self.emit(TrackEventKind::Terminated, track_id);
+            self.tracks.remove(&track_id);
+        }
+
+        tracked
+    }
+
+    /// Predictions are not a part of trajectories, tracks are only checked for being lost on updates.
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.frame += 1;
+        self.epoch += self.clock.tick_epochs(timestamp);
+        self.tracker.predict(frame_dims, timestamp)
+    }
+
+    fn finish(&mut self) {
+        self.tracker.finish();
+        let track_ids: Vec<_> = self.tracks.keys().copied().collect();
+        for track_id in track_ids {
+            self.emit(TrackEventKind::Terminated, track_id);
+        }
+        self.tracks.clear();
+    }
+}
+
+/// Trajectory point, serialized as a part of [TrackRecord].
+#[derive(Serialize)]
+struct PointRecord {
+    frame: u64,
+    pts_ns: Option<u64>,
+    xmin: f32,
+    ymin: f32,
+    xmax: f32,
+    ymax: f32,
+    confidence: f32,
+}
+
+/// A whole track, serialized as one JSON Lines record.
+#[derive(Serialize)]
+struct TrackRecord<'a> {
+    id: i64,
+    class_id: usize,
+    class: std::borrow::Cow<'a, str>,
+    first_frame: u64,
+    last_frame: u64,
+    first_pts_ns: Option<u64>,
+    last_pts_ns: Option<u64>,
+    trajectory: Vec<PointRecord>,
+}
+
+fn nanos(timestamp: Option<Duration>) -> Option<u64> {
+    timestamp.map(|ts| ts.as_nanos() as u64)
+}
+
+/// Writes per-object records of terminated tracks as JSON Lines, one JSON object per track.
+pub struct TrackRecordWriter<W: Write> {
+    writer: W,
+}
+
+impl TrackRecordWriter<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path` to write tracks into.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .map_err(|err| anyhow::anyhow!("Failed to create {path:?}: {err}"))?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> TrackRecordWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Writes the track of `event` as a single line, its trajectory is written as is.
+    pub fn write_track(&mut self, event: &TrackEvent, labels: &LabelMap) -> anyhow::Result<()> {
+        let record = TrackRecord {
+            id: event.track_id,
+            class_id: event.class,
+            class: labels.name(event.class),
+            first_frame: event.first_frame,
+            last_frame: event.last_frame,
+            first_pts_ns: nanos(event.first_timestamp),
+            last_pts_ns: nanos(event.last_timestamp),
+            trajectory: event
+                .trajectory
+                .iter()
+                .map(|point| PointRecord {
+                    frame: point.frame,
+                    pts_ns: nanos(point.timestamp),
+                    xmin: point.bbox.xmin,
+                    ymin: point.bbox.ymin,
+                    xmax: point.bbox.xmax,
+                    ymax: point.bbox.ymax,
+                    confidence: point.bbox.detector_confidence,
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn lifecycle_events_follow_tracks() {
+    use crate::{test_detections, IouTracker, TrackerConfig};
+
+    let config = TrackerConfig {
+        max_idle_epochs: 1,
+        ..Default::default()
+    };
+    let tracker = Box::new(IouTracker::new(&config));
+    let (mut tracker, events) = LifecycleTracker::with_channel(tracker, &config);
+    let mut pts_ms = 0;
+    let mut kinds = |detections: &[(f32, usize)], frames: u64| {
+        pts_ms += 40 * frames;
+        let timestamp = Some(Duration::from_millis(pts_ms));
+        tracker.update(&test_detections(detections), timestamp);
+        events
+            .try_iter()
+            .map(|event| event.kind)
+            .collect::<Vec<_>>()
+    };
+
+    use TrackEventKind::*;
+    let bus = [(100.0, 2)];
+    assert_eq!(kinds(&bus, 1), [Created]);
+    assert_eq!(kinds(&bus, 1), [Updated]);
+    assert_eq!(kinds(&[], 1), [Lost]);
+    assert_eq!(kinds(&bus, 1), [Updated]);
+    assert_eq!(kinds(&[], 1), [Lost]);
+    assert_eq!(kinds(&[], 1), [Terminated]);
+    // Dropped frames count as idle epochs, as they do for the wrapped tracker.
+    assert_eq!(kinds(&bus, 1), [Created]);
+    assert_eq!(kinds(&[], 3), [Terminated]);
+
+    tracker.update(&test_detections(&bus), None);
+    tracker.finish();
+    let events: Vec<_> = events.try_iter().collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].kind, Terminated);
+    assert_eq!(events[1].class, 2);
+    assert_eq!((events[1].first_frame, events[1].last_frame), (8, 8));
+}
@@ -0,0 +1,136 @@
+//! IoU tracker, which matches detections with the last observed bboxes of tracks,
+//! without any motion model, see <http://elvera.nue.tu-berlin.de/files/1517Bochinski2017.pdf>.
+//!
+//! Cheap and good enough for high frame rate inputs with slow moving objects.
+
+use std::time::Duration;
+
+use gstreamed_common::assignment::linear_assignment;
+use gstreamed_common::bbox::{iou, Bbox, Detections};
+use gstreamed_common::img_dimensions::ImgDimensions;
+
+use crate::clock::FrameClock;
+use crate::{observed, predicted, Tracker, TrackerConfig};
+
+#[derive(Debug, Clone)]
+struct Track {
+    id: i64,
+    /// Last observed bbox.
+    bbox: Bbox,
+    /// Epoch the track was last observed on.
+    last_epoch: u64,
+}
+
+/// IoU tracker, see module docs.
+///
+/// Uses [TrackerConfig::iou_threshold] for matching, [TrackerConfig::max_idle_epochs]
+/// and [TrackerConfig::frame_rate], the rest of the parameters are ignored.
+pub struct IouTracker {
+    iou_threshold: f32,
+    max_idle_epochs: u64,
+    clock: FrameClock,
+    tracks: Vec<Track>,
+    epoch: u64,
+    /// Epoch of the latest [Tracker::update].
+    update_epoch: u64,
+    next_id: i64,
+}
+
+impl IouTracker {
+    pub fn new(config: &TrackerConfig) -> Self {
+        Self {
+            iou_threshold: config.iou_threshold,
+            max_idle_epochs: config.max_idle_epochs as u64,
+            clock: FrameClock::new(config.frame_rate),
+            tracks: Vec::new(),
+            epoch: 0,
+            update_epoch: 0,
+            next_id: 1,
+        }
+    }
+}
+
+impl Tracker for IouTracker {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        self.epoch += self.clock.tick_epochs(timestamp);
+        self.update_epoch = self.epoch;
+        let cost: Vec<Vec<f64>> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                detections
+                    .iter()
+                    .map(|det| 1.0 - iou(&track.bbox, det) as f64)
+                    .collect()
+            })
+            .collect();
+        let max_cost = 1.0 - self.iou_threshold as f64;
+        let mut matched = vec![None; detections.len()];
+        for (t, d) in linear_assignment(&cost) {
+            if cost[t][d] <= max_cost {
+                matched[d] = Some(t);
+            }
+        }
+
+        let mut tracked = Vec::with_capacity(detections.len());
+        for (det, track) in detections.iter().zip(matched) {
+            let t = track.unwrap_or_else(|| {
+                self.tracks.push(Track {
+                    id: self.next_id,
+                    bbox: det.clone(),
+                    last_epoch: self.epoch,
+                });
+                self.next_id += 1;
+                self.tracks.len() - 1
+            });
+            let track = &mut self.tracks[t];
+            track.bbox = det.clone();
+            track.last_epoch = self.epoch;
+            tracked.push(Bbox {
+                tracker_confidence: det.detector_confidence,
+                tracker_id: Some(track.id),
+                observed: Some(observed(det)),
+                ..det.clone()
+            });
+        }
+
+        let (epoch, max_idle) = (self.epoch, self.max_idle_epochs);
+        self.tracks
+            .retain(|track| epoch - track.last_epoch <= max_idle);
+        detections.with_bboxes(tracked)
+    }
+
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.epoch += self.clock.tick_epochs(timestamp);
+        let predictions = self
+            .tracks
+            .iter()
+            .filter(|track| track.last_epoch == self.update_epoch)
+            .map(|track| {
+                let bbox = Bbox {
+                    tracker_confidence: track.bbox.detector_confidence,
+                    tracker_id: Some(track.id),
+                    ..track.bbox.clone()
+                };
+                predicted(bbox, frame_dims)
+            })
+            .collect();
+        Detections::from_bboxes(predictions, frame_dims)
+    }
+}
+
+#[test]
+fn iou_tracker_matches_overlapping_bboxes() {
+    use crate::{test_detections, test_track};
+
+    let mut tracker = IouTracker::new(&TrackerConfig::default());
+
+    let detections = test_detections(&[(0.0, 0), (300.0, 0)]);
+    assert_eq!(test_track(&mut tracker, &detections, &[]), [(0, 1), (0, 2)]);
+    // Detection order doesn't matter, new object gets a new id.
+    let detections = test_detections(&[(310.0, 0), (500.0, 0), (10.0, 0)]);
+    assert_eq!(
+        test_track(&mut tracker, &detections, &[]),
+        [(0, 2), (0, 3), (0, 1)]
+    );
+}
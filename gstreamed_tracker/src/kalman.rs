@@ -0,0 +1,160 @@
+//! Constant velocity Kalman filter of bbox center, aspect ratio and height, as in SORT,
+//! but predicting over any number of epochs, not just a single one.
+//!
+//! Noise is modelled like in similari's `Universal2DBoxKalmanFilter`, proportional to the bbox height,
+//! with the process noise growing with the elapsed time.
+
+use nalgebra::{SMatrix, SVector};
+
+/// Measured dimensions: center x, center y, aspect ratio (width / height) and height.
+const DIM: usize = 4;
+/// Measured dimensions and their velocities.
+const DIM_X2: usize = DIM * 2;
+
+/// Bbox as measured by the filter, `[xc, yc, aspect, height]`.
+type Measurement = SVector<f32, DIM>;
+
+/// Estimated bbox state with its uncertainty.
+#[derive(Debug, Clone, Copy)]
+pub struct KalmanState {
+    mean: SVector<f32, DIM_X2>,
+    covariance: SMatrix<f32, DIM_X2, DIM_X2>,
+}
+
+impl KalmanState {
+    /// Estimated bbox as `[xmin, ymin, xmax, ymax]`, or `None` if it has degenerated,
+    /// which can happen when predicting far ahead with a shrinking velocity.
+    pub fn ltrb(&self) -> Option<[f32; 4]> {
+        let [xc, yc, aspect, height] = [self.mean[0], self.mean[1], self.mean[2], self.mean[3]];
+        let width = aspect * height;
+        if !(width > 0.0 && height > 0.0 && xc.is_finite() && yc.is_finite()) {
+            return None;
+        }
+        Some([
+            xc - width / 2.0,
+            yc - height / 2.0,
+            xc + width / 2.0,
+            yc + height / 2.0,
+        ])
+    }
+}
+
+/// Kalman filter, see module docs.
+#[derive(Debug, Clone)]
+pub struct KalmanFilter {
+    position_weight: f32,
+    velocity_weight: f32,
+}
+
+impl KalmanFilter {
+    /// Creates the filter with the given position and velocity noise weights.
+    pub fn new(position_weight: f32, velocity_weight: f32) -> Self {
+        Self {
+            position_weight,
+            velocity_weight,
+        }
+    }
+
+    fn measurement([xmin, ymin, xmax, ymax]: [f32; 4]) -> Measurement {
+        let height = ymax - ymin;
+        Measurement::new(
+            (xmin + xmax) / 2.0,
+            (ymin + ymax) / 2.0,
+            (xmax - xmin) / height,
+            height,
+        )
+    }
+
+    /// Noise std of measured dimensions, aspect ratio noise is constant.
+    fn std_position(&self, k: f32, aspect: f32, height: f32) -> [f32; DIM] {
+        let std = k * self.position_weight * height;
+        [std, std, aspect, std]
+    }
+
+    /// Noise std of velocities, aspect ratio velocity noise is constant.
+    fn std_velocity(&self, k: f32, aspect: f32, height: f32) -> [f32; DIM] {
+        let std = k * self.velocity_weight * height;
+        [std, std, aspect, std]
+    }
+
+    fn variance(std: impl IntoIterator<Item = f32>) -> SMatrix<f32, DIM_X2, DIM_X2> {
+        let std = SVector::<f32, DIM_X2>::from_iterator(std);
+        SMatrix::from_diagonal(&std.component_mul(&std))
+    }
+
+    /// Starts tracking the `[xmin, ymin, xmax, ymax]` bbox, with zero velocity.
+    pub fn initiate(&self, bbox: [f32; 4]) -> KalmanState {
+        let measurement = Self::measurement(bbox);
+        let height = measurement[3];
+        let mut mean = SVector::<f32, DIM_X2>::zeros();
+        mean.fixed_rows_mut::<DIM>(0).copy_from(&measurement);
+        let std = self
+            .std_position(2.0, 1e-2, height)
+            .into_iter()
+            .chain(self.std_velocity(10.0, 1e-5, height));
+        KalmanState {
+            mean,
+            covariance: Self::variance(std),
+        }
+    }
+
+    /// Predicts the state `epochs` ahead, fractions of epochs are fine.
+    pub fn predict(&self, state: &KalmanState, epochs: f32) -> KalmanState {
+        let height = state.mean[3];
+        let mut motion = SMatrix::<f32, DIM_X2, DIM_X2>::identity();
+        for i in 0..DIM {
+            motion[(i, DIM + i)] = epochs;
+        }
+        let std = self
+            .std_position(1.0, 1e-2, height)
+            .into_iter()
+            .chain(self.std_velocity(1.0, 1e-5, height));
+        // Noise of independent steps adds up, so the variance grows linearly with time.
+        let motion_noise = Self::variance(std) * epochs;
+        KalmanState {
+            mean: motion * state.mean,
+            covariance: motion * state.covariance * motion.transpose() + motion_noise,
+        }
+    }
+
+    /// Corrects the (predicted) state with the observed `[xmin, ymin, xmax, ymax]` bbox.
+    pub fn update(&self, state: &KalmanState, bbox: [f32; 4]) -> KalmanState {
+        let measurement = Self::measurement(bbox);
+        let height = state.mean[3];
+        let std = SVector::<f32, DIM>::from(self.std_position(1.0, 1e-1, height));
+        let innovation_noise = SMatrix::from_diagonal(&std.component_mul(&std));
+
+        // Measurement matrix just picks the first half of the state.
+        let projected_covariance = state.covariance.fixed_view::<DIM, DIM>(0, 0) + innovation_noise;
+        let Some(inverse) = projected_covariance.try_inverse() else {
+            return *state;
+        };
+        let gain = state.covariance.fixed_view::<DIM_X2, DIM>(0, 0) * inverse;
+        let innovation = measurement - state.mean.fixed_rows::<DIM>(0);
+        KalmanState {
+            mean: state.mean + gain * innovation,
+            covariance: state.covariance - gain * projected_covariance * gain.transpose(),
+        }
+    }
+}
+
+#[test]
+fn kalman_prediction_scales_with_elapsed_epochs() {
+    let filter = KalmanFilter::new(1.0 / 20.0, 1.0 / 160.0);
+    let bbox = |x: f32| [x, 100.0, x + 50.0, 200.0];
+    // Object moving 10 px per epoch.
+    let mut state = filter.initiate(bbox(0.0));
+    for x in 1..30 {
+        state = filter.predict(&state, 1.0);
+        state = filter.update(&state, bbox(x as f32 * 10.0));
+    }
+    let xmin = |state: &KalmanState| state.ltrb().unwrap()[0];
+    assert!((xmin(&state) - 290.0).abs() < 2.0);
+
+    let one = filter.predict(&state, 1.0);
+    let three = filter.predict(&state, 3.0);
+    let half = filter.predict(&state, 0.5);
+    assert!((xmin(&one) - 300.0).abs() < 2.0);
+    assert!((xmin(&three) - 320.0).abs() < 3.0);
+    assert!((xmin(&half) - 295.0).abs() < 2.0);
+}
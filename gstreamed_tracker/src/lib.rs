@@ -1,110 +1,154 @@
 //! Common tracker interface, params etc.
 
-use std::sync::Mutex;
+use std::time::Duration;
 
-use gstreamed_common::bbox::Bbox;
-use gstreamed_common::coco_classes;
+use gstreamed_common::assignment::linear_assignment;
+use gstreamed_common::bbox::{Bbox, Detections};
 use gstreamed_common::img_dimensions::ImgDimensions;
-use similari::prelude::PositionalMetricType::IoU;
-use similari::prelude::{SortTrack, Universal2DBox};
-use similari::trackers::sort::metric::DEFAULT_MINIMAL_SORT_CONFIDENCE;
-use similari::{prelude::Sort, trackers::sort::DEFAULT_SORT_IOU_THRESHOLD};
 
+pub mod byte_track;
+pub mod classes;
+pub mod clock;
+pub mod config;
+pub mod events;
+pub mod iou;
+pub mod kalman;
+pub mod sort;
+pub mod visual_sort;
+
+pub use byte_track::ByteTrack;
+pub use classes::{ClassMode, ClassVoteTracker, PerClassTracker};
+pub use config::{TrackerConfig, TrackerKind};
+pub use events::{LifecycleTracker, TrackEvent, TrackEventKind};
+pub use iou::IouTracker;
+pub use sort::SortTracker;
+pub use visual_sort::VisualSortTracker;
 // `similari` re-export so types can be named etc.
 pub use similari;
 
-/// Creates a SORT tracker with some default params, largely untuned.
-pub fn sort_tracker() -> Mutex<Sort> {
-    Mutex::new(Sort::new(
-        1,
-        1,
-        10,
-        IoU(DEFAULT_SORT_IOU_THRESHOLD),
-        DEFAULT_MINIMAL_SORT_CONFIDENCE,
-        None,
-        1.0 / 20.0,
-        1.0 / 160.0,
-    ))
-}
+/// Multi-object tracker, which assigns track ids to detections of consecutive frames.
+pub trait Tracker: Send {
+    /// Updates tracks with `detections` of the next frame, observed at `timestamp`, if known.
+    ///
+    /// Returns tracked bboxes of this frame, with their `tracker_id` set.
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections;
+
+    /// Same as [Tracker::update], with appearance `features` of each detection,
+    /// see [gstreamed_common::embedder::Embedder].
+    ///
+    /// Trackers which don't use appearance ignore the features.
+    fn update_with_features(
+        &mut self,
+        detections: &Detections,
+        features: &[Vec<f32>],
+        timestamp: Option<Duration>,
+    ) -> Detections {
+        let _ = features;
+        self.update(detections, timestamp)
+    }
 
-/// Maps from [SortTrack] back to our [Bbox].
-pub fn tracks_to_bboxes(tracks: &[SortTrack], scaled_dims: ImgDimensions) -> Vec<Bbox> {
-    let mut out = Vec::with_capacity(tracks.len());
-    for track in tracks {
-        let tracked_bbox = &track.predicted_bbox;
-        // We use similari custom object id support for class ids.
-        let class_id = track.custom_object_id.unwrap();
-        let id = track.id;
-
-        // Map from similary bbox to our bbox.
-        let cx = tracked_bbox.xc;
-        let cy = tracked_bbox.yc;
-        let h = tracked_bbox.height;
-        let aspect = tracked_bbox.aspect;
-        let w = aspect * h;
-
-        let xmin = cx - w / 2f32;
-        let ymin = cy - h / 2f32;
-        let xmax = xmin + w;
-        let ymax = ymin + h;
-
-        out.push(Bbox {
-            xmin: xmin.max(0.0f32).min(scaled_dims.width),
-            ymin: ymin.max(0.0f32).min(scaled_dims.height),
-            xmax: xmax.max(0.0f32).min(scaled_dims.width),
-            ymax: ymax.max(0.0f32).min(scaled_dims.height),
-            // FIXME this, unfortunately, does not retain og yolo confidence...
-            detector_confidence: track.observed_bbox.confidence,
-            // FIXME tracker confidence is always very high?
-            //  does it not work for sort?
-            tracker_confidence: tracked_bbox.confidence,
-            data: vec![],
-            class: class_id as usize,
-            tracker_id: Some(id as i64),
-        });
+    /// Advances tracks to the next frame, observed at `timestamp`, without running the detector on it,
+    /// e.g. when detecting only on every n-th frame.
+    ///
+    /// Returns bboxes of the tracks seen on the latest [Tracker::update], as predicted for this frame,
+    /// with `predicted` set. Trackers without a motion model keep the last bboxes.
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        let _ = timestamp;
+        Detections::new(frame_dims)
     }
-    out
+
+    /// Called once the stream has ended, after the last [Tracker::update].
+    fn finish(&mut self) {}
 }
 
-/// Predicts [SortTrack]s using the given [Sort] tracker and
-/// observed `bboxes_per_class`.
-pub fn predict_tracks(tracker: &mut Sort, bboxes_per_class: &[Vec<Bbox>]) -> Vec<SortTrack> {
-    let bboxes_4_tracking: Vec<_> = bboxes_per_class
-        .iter()
-        .flatten()
-        .map(|bbox| {
-            (
-                Universal2DBox::ltwh(
-                    bbox.xmin,
-                    bbox.ymin,
-                    bbox.xmax - bbox.xmin,
-                    bbox.ymax - bbox.ymin,
-                ),
-                Some(bbox.class as i64),
-            )
-        })
-        .collect();
-    tracker.predict(&bboxes_4_tracking)
+/// Tracker which doesn't track, detections are passed through as is.
+#[derive(Debug, Default)]
+pub struct NoopTracker;
+
+impl Tracker for NoopTracker {
+    fn update(&mut self, detections: &Detections, _timestamp: Option<Duration>) -> Detections {
+        detections.clone()
+    }
 }
 
-/// Predicts tracked [Bbox]es from the input `bboxes_per_class`,
-/// using the given `tracker` and `scaled_dims`.
-pub fn predict_tracked_bboxes(
-    tracker: &mut Sort,
-    scaled_dims: ImgDimensions,
-    bboxes_per_class: &[Vec<Bbox>],
-) -> Vec<Bbox> {
-    let tracks = predict_tracks(tracker, bboxes_per_class);
-    log::trace!("{tracks:?}");
-    tracks_to_bboxes(&tracks, scaled_dims)
+/// Creates the tracker selected by [TrackerConfig::kind], configured by `config`,
+/// handling classes as set by [TrackerConfig::class_mode].
+pub fn tracker(config: &TrackerConfig) -> Box<dyn Tracker> {
+    log::info!("{config:?}");
+    match config.class_mode {
+        ClassMode::Agnostic => class_agnostic_tracker(config),
+        ClassMode::Strict => Box::new(PerClassTracker::new(config, class_agnostic_tracker)),
+        ClassMode::Vote => Box::new(ClassVoteTracker::new(
+            class_agnostic_tracker(config),
+            config,
+        )),
+    }
 }
 
-// TODO introduce proper type for Vec<Vec<Bbox>>...
-/// Transform a flat list of [Bbox] back into bboxes grouped by class.
-pub fn unflatten_bboxes(flat_bboxes: Vec<Bbox>) -> Vec<Vec<Bbox>> {
-    let mut bboxes_by_class = vec![Vec::new(); coco_classes::NAMES.len()];
-    for tracked_bbox in flat_bboxes {
-        bboxes_by_class[tracked_bbox.class].push(tracked_bbox);
+/// Creates the tracker selected by [TrackerConfig::kind], ignoring [TrackerConfig::class_mode].
+pub(crate) fn class_agnostic_tracker(config: &TrackerConfig) -> Box<dyn Tracker> {
+    match config.kind {
+        TrackerKind::Sort => Box::new(SortTracker::new(config)),
+        TrackerKind::Iou => Box::new(IouTracker::new(config)),
+        TrackerKind::VisualSort => Box::new(VisualSortTracker::new(config)),
+        TrackerKind::ByteTrack => Box::new(ByteTrack::new(config)),
+        TrackerKind::None => Box::new(NoopTracker),
     }
-    bboxes_by_class
+}
+
+/// Cost of pairs which must not be matched, see [assign].
+pub(crate) const INVALID_COST: f64 = 1e6;
+
+/// Matches rows with columns of the `cost` matrix with minimal total cost, leaving out pairs of [INVALID_COST].
+pub(crate) fn assign(cost: &[Vec<f64>]) -> Vec<(usize, usize)> {
+    linear_assignment(cost)
+        .into_iter()
+        .filter(|&(row, col)| cost[row][col] < INVALID_COST)
+        .collect()
+}
+
+/// Marks the tracked `bbox` as a prediction for a frame without detection, see [Tracker::predict].
+pub(crate) fn predicted(bbox: Bbox, frame_dims: ImgDimensions) -> Bbox {
+    let mut bbox = Bbox {
+        observed: None,
+        predicted: true,
+        ..bbox
+    };
+    bbox.clamp(frame_dims);
+    bbox
+}
+
+/// Detected bbox coordinates, see [Bbox::observed].
+pub(crate) fn observed(detection: &Bbox) -> [f32; 4] {
+    [
+        detection.xmin,
+        detection.ymin,
+        detection.xmax,
+        detection.ymax,
+    ]
+}
+
+/// Test detections of `(x, class)` pairs, as 50x100 bboxes at `x`, on a 640x480 frame.
+#[cfg(test)]
+pub(crate) fn test_detections(bboxes: &[(f32, usize)]) -> Detections {
+    let bboxes = bboxes
+        .iter()
+        .map(|&(x, class)| Bbox::test(x, 100.0, x + 50.0, 200.0, class))
+        .collect();
+    Detections::from_bboxes(bboxes, ImgDimensions::new(640.0, 480.0))
+}
+
+/// Updates the `tracker` with `detections` and their appearance `features`, if any,
+/// returning `(class, tracker id)` of the tracked bboxes.
+#[cfg(test)]
+pub(crate) fn test_track(
+    tracker: &mut dyn Tracker,
+    detections: &Detections,
+    features: &[Vec<f32>],
+) -> Vec<(usize, i64)> {
+    tracker
+        .update_with_features(detections, features, None)
+        .iter()
+        .map(|bbox| (bbox.class, bbox.tracker_id.unwrap()))
+        .collect()
 }
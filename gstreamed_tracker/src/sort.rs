@@ -0,0 +1,322 @@
+//! SORT tracker, see <https://arxiv.org/abs/1602.00763>.
+//!
+//! Follows similari's SORT: detections are matched with the tracks predicted by the [KalmanFilter]
+//! by IoU weighted by detection confidence, and each unmatched detection starts a new track.
+//! Unlike similari, the filter predicts over the time elapsed between frames, see [FrameClock],
+//! so dropped frames and frames without detection don't throw it off.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use gstreamed_common::bbox::{iou, Bbox, Detections};
+use gstreamed_common::img_dimensions::ImgDimensions;
+
+use crate::clock::{whole_epochs, FrameClock};
+use crate::kalman::{KalmanFilter, KalmanState};
+use crate::{assign, observed, predicted, Tracker, TrackerConfig, INVALID_COST};
+
+/// Track of [SortTracks].
+#[derive(Debug, Clone)]
+pub(crate) struct SortTrack {
+    pub(crate) id: i64,
+    kalman: KalmanState,
+    /// Epoch the track was last observed on.
+    last_epoch: u64,
+    /// Last observed detection.
+    detection: Bbox,
+    /// Appearance features of the latest observations, oldest first, only collected by Visual SORT.
+    pub(crate) features: VecDeque<Vec<f32>>,
+}
+
+impl SortTrack {
+    /// Current Kalman state of the track as a bbox, with the attributes of the last detection.
+    ///
+    /// Falls back to the last detection, if the state has degenerated into an invalid bbox.
+    fn bbox(&self) -> Bbox {
+        let [xmin, ymin, xmax, ymax] = self
+            .kalman
+            .ltrb()
+            .unwrap_or_else(|| observed(&self.detection));
+        Bbox {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+            tracker_confidence: self.detection.detector_confidence,
+            tracker_id: Some(self.id),
+            observed: Some(observed(&self.detection)),
+            ..self.detection.clone()
+        }
+    }
+}
+
+/// Tracks and their motion, shared by [SortTracker] and [crate::VisualSortTracker].
+pub(crate) struct SortTracks {
+    filter: KalmanFilter,
+    clock: FrameClock,
+    iou_threshold: f32,
+    min_confidence: f32,
+    max_idle_epochs: u64,
+    pub(crate) tracks: Vec<SortTrack>,
+    /// Epochs elapsed since the start, counting the current frame.
+    epoch: u64,
+    /// Epoch of the latest [SortTracks::update].
+    update_epoch: u64,
+    next_id: i64,
+}
+
+impl SortTracks {
+    pub(crate) fn new(config: &TrackerConfig) -> Self {
+        Self {
+            filter: KalmanFilter::new(config.position_weight, config.velocity_weight),
+            clock: FrameClock::new(config.frame_rate),
+            iou_threshold: config.iou_threshold,
+            min_confidence: config.min_confidence,
+            max_idle_epochs: config.max_idle_epochs as u64,
+            tracks: Vec::new(),
+            epoch: 0,
+            update_epoch: 0,
+            next_id: 1,
+        }
+    }
+
+    /// Advances to the frame at `timestamp`, predicting motion of all tracks over the elapsed time.
+    pub(crate) fn advance(&mut self, timestamp: Option<Duration>) {
+        let elapsed = self.clock.tick(timestamp);
+        self.epoch += whole_epochs(elapsed);
+        for track in &mut self.tracks {
+            track.kalman = self.filter.predict(&track.kalman, elapsed);
+        }
+    }
+
+    /// Matches `tracks` with `detections` (indices into `self.tracks` and `all_detections`)
+    /// by IoU of predicted bboxes, weighted by detection confidence of at least [TrackerConfig::min_confidence].
+    ///
+    /// Returns matched `(track, detection)` index pairs.
+    pub(crate) fn associate(
+        &self,
+        tracks: &[usize],
+        detections: &[usize],
+        all_detections: &Detections,
+    ) -> Vec<(usize, usize)> {
+        let all_detections = all_detections.as_slice();
+        let cost: Vec<Vec<f64>> = tracks
+            .iter()
+            .map(|&t| {
+                let track = self.tracks[t].bbox();
+                detections
+                    .iter()
+                    .map(|&d| {
+                        let det = &all_detections[d];
+                        let weight = det.detector_confidence.max(self.min_confidence);
+                        let similarity = iou(&track, det) * weight;
+                        if similarity >= self.iou_threshold {
+                            1.0 - similarity as f64
+                        } else {
+                            INVALID_COST
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        assign(&cost)
+            .into_iter()
+            .map(|(t, d)| (tracks[t], detections[d]))
+            .collect()
+    }
+
+    /// Updates tracks with their `matches` among `detections`, and starts new tracks with the unmatched ones,
+    /// keeping up to `max_features` of their `features`, if given.
+    ///
+    /// Returns tracked bboxes of all `detections`, in the same order.
+    pub(crate) fn update(
+        &mut self,
+        detections: &Detections,
+        matches: &[(usize, usize)],
+        features: &[Vec<f32>],
+        max_features: usize,
+    ) -> Detections {
+        self.update_epoch = self.epoch;
+        let mut matched = vec![None; detections.len()];
+        for &(t, d) in matches {
+            matched[d] = Some(t);
+        }
+
+        let mut tracked = Vec::with_capacity(detections.len());
+        for (d, (detection, track)) in detections.iter().zip(matched).enumerate() {
+            let t = match track {
+                Some(t) => {
+                    let track = &mut self.tracks[t];
+                    track.kalman = self.filter.update(&track.kalman, observed(detection));
+                    track.last_epoch = self.epoch;
+                    track.detection = detection.clone();
+                    t
+                }
+                None => {
+                    self.tracks.push(SortTrack {
+                        id: self.next_id,
+                        kalman: self.filter.initiate(observed(detection)),
+                        last_epoch: self.epoch,
+                        detection: detection.clone(),
+                        features: VecDeque::new(),
+                    });
+                    self.next_id += 1;
+                    self.tracks.len() - 1
+                }
+            };
+            let track = &mut self.tracks[t];
+            if let Some(feature) = features.get(d) {
+                track.features.push_back(feature.clone());
+                if track.features.len() > max_features {
+                    track.features.pop_front();
+                }
+            }
+            let mut bbox = track.bbox();
+            bbox.clamp(detections.frame_dims);
+            tracked.push(bbox);
+        }
+
+        // Tracks are removed after they've been idle for too long.
+        let (epoch, max_idle) = (self.epoch, self.max_idle_epochs);
+        self.tracks
+            .retain(|track| epoch - track.last_epoch <= max_idle);
+        detections.with_bboxes(tracked)
+    }
+
+    /// Advances to the frame at `timestamp`, see [Tracker::predict].
+    pub(crate) fn predict(
+        &mut self,
+        frame_dims: ImgDimensions,
+        timestamp: Option<Duration>,
+    ) -> Detections {
+        self.advance(timestamp);
+        let predictions = self
+            .tracks
+            .iter()
+            .filter(|track| track.last_epoch == self.update_epoch)
+            .map(|track| predicted(track.bbox(), frame_dims))
+            .collect();
+        Detections::from_bboxes(predictions, frame_dims)
+    }
+}
+
+/// SORT [Tracker], see module docs.
+///
+/// Tracks are kept for [TrackerConfig::max_idle_epochs] epochs of the frame interval,
+/// see [FrameClock], so dropped frames count as idle epochs as well.
+pub struct SortTracker {
+    tracks: SortTracks,
+}
+
+impl SortTracker {
+    pub fn new(config: &TrackerConfig) -> Self {
+        Self {
+            tracks: SortTracks::new(config),
+        }
+    }
+}
+
+impl Tracker for SortTracker {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        self.tracks.advance(timestamp);
+        let tracks: Vec<_> = (0..self.tracks.tracks.len()).collect();
+        let all: Vec<_> = (0..detections.len()).collect();
+        let matches = self.tracks.associate(&tracks, &all, detections);
+        self.tracks.update(detections, &matches, &[], 0)
+    }
+
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.tracks.predict(frame_dims, timestamp)
+    }
+}
+
+#[test]
+fn sort_keeps_detection_attributes() {
+    use gstreamed_common::bbox::KeyPoint;
+    use gstreamed_common::img_dimensions::ImgDimensions;
+
+    let detection = |x: f32, confidence: f32, class: usize| Bbox {
+        detector_confidence: confidence,
+        data: vec![KeyPoint {
+            x: x + 25.0,
+            y: 120.0,
+            mask: 1.0,
+        }],
+        ..Bbox::test(x, 100.0, x + 50.0, 200.0, class)
+    };
+    let frame_dims = ImgDimensions::new(640.0, 480.0);
+    let mut tracker = SortTracker::new(&TrackerConfig::default());
+    for x in [100.0, 104.0, 108.0] {
+        // Two objects moving in opposite directions, with different confidences and classes.
+        let detections = vec![detection(400.0 - x, 0.4, 2), detection(x, 0.8, 0)];
+        let tracked = tracker.update(
+            &Detections::from_bboxes(detections.clone(), frame_dims),
+            None,
+        );
+        assert_eq!(tracked.len(), 2);
+        for (bbox, detection) in tracked.iter().zip(&detections) {
+            assert_eq!(bbox.detector_confidence, detection.detector_confidence);
+            assert_eq!(bbox.class, detection.class);
+            assert_eq!(bbox.data, detection.data);
+            assert_eq!(
+                bbox.observed,
+                Some([
+                    detection.xmin,
+                    detection.ymin,
+                    detection.xmax,
+                    detection.ymax
+                ])
+            );
+        }
+        assert_eq!(tracked.as_slice()[1].tracker_id, Some(2));
+    }
+}
+
+#[test]
+fn sort_predicts_tracks_between_detections() {
+    use gstreamed_common::img_dimensions::ImgDimensions;
+
+    let frame_dims = ImgDimensions::new(640.0, 480.0);
+    let detection = |x: f32| Bbox::test(x, 100.0, x + 50.0, 200.0, 0);
+    let mut tracker = SortTracker::new(&TrackerConfig::default());
+    let mut xmin = 0.0;
+    for x in [100.0, 110.0, 120.0] {
+        let detections = Detections::from_bboxes(vec![detection(x)], frame_dims);
+        xmin = tracker.update(&detections, None).as_slice()[0].xmin;
+    }
+
+    // Object keeps moving right on frames without detection.
+    for _ in 0..2 {
+        let predicted = tracker.predict(frame_dims, None);
+        let [bbox] = predicted.as_slice() else {
+            panic!("Expected a single prediction, got {predicted:?}");
+        };
+        assert!(bbox.predicted);
+        assert_eq!((bbox.tracker_id, bbox.observed), (Some(1), None));
+        assert!(bbox.xmin > xmin);
+        xmin = bbox.xmin;
+    }
+    let detections = Detections::from_bboxes(vec![detection(140.0)], frame_dims);
+    let tracked = tracker.update(&detections, None);
+    assert_eq!(tracked.as_slice()[0].tracker_id, Some(1));
+    assert!(!tracked.as_slice()[0].predicted);
+}
+
+#[test]
+fn sort_predicts_over_dropped_frames() {
+    use gstreamed_common::img_dimensions::ImgDimensions;
+
+    let frame_dims = ImgDimensions::new(1280.0, 480.0);
+    let detection = |x: f32| Bbox::test(x, 100.0, x + 50.0, 200.0, 0);
+    let ms = |ms| Some(Duration::from_millis(ms));
+    let mut tracker = SortTracker::new(&TrackerConfig::default());
+    // Object moving 20 px per 40 ms frame.
+    for frame in 0..10 {
+        let detections = Detections::from_bboxes(vec![detection(frame as f32 * 20.0)], frame_dims);
+        tracker.update(&detections, ms(frame * 40));
+    }
+    // 3 frames dropped, the object moved 80 px, further than its width from a single step prediction.
+    let detections = Detections::from_bboxes(vec![detection(260.0)], frame_dims);
+    let tracked = tracker.update(&detections, ms(13 * 40));
+    assert_eq!(tracked.as_slice()[0].tracker_id, Some(1));
+}
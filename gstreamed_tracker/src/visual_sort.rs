@@ -0,0 +1,167 @@
+//! Visual SORT tracker, which combines SORT with appearance features, like similari's `VisualSort`.
+//!
+//! Tracks are matched by appearance features (ReID embeddings), once they have collected enough of them,
+//! so they keep their ids across short occlusions and in crowds, where IoU alone is ambiguous.
+//! Observations without features, and the ones left unmatched by appearance, are matched by position,
+//! like in [crate::SortTracker].
+
+use std::time::Duration;
+
+use gstreamed_common::bbox::Detections;
+use gstreamed_common::img_dimensions::ImgDimensions;
+use serde::Deserialize;
+
+use crate::sort::SortTracks;
+use crate::{assign, Tracker, TrackerConfig, INVALID_COST};
+
+/// Visual SORT specific parameters, the rest are shared with SORT in [TrackerConfig].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VisualSortConfig {
+    /// Minimal cosine similarity of features for a detection to be matched with a track.
+    pub min_similarity: f32,
+    /// How many features of each track are kept to compare with.
+    pub max_observations: usize,
+    /// How many features a track needs to collect before it is matched by appearance.
+    pub min_track_length: usize,
+    /// How many of the track features have to agree for a match.
+    pub min_votes: usize,
+}
+
+impl Default for VisualSortConfig {
+    fn default() -> Self {
+        Self {
+            min_similarity: 0.5,
+            max_observations: 5,
+            min_track_length: 3,
+            min_votes: 1,
+        }
+    }
+}
+
+/// Visual SORT [Tracker], see module docs.
+///
+/// Like [crate::SortTracker], motion is predicted over the time elapsed between frames
+/// and dropped frames count as idle epochs.
+pub struct VisualSortTracker {
+    config: VisualSortConfig,
+    tracks: SortTracks,
+}
+
+impl VisualSortTracker {
+    pub fn new(config: &TrackerConfig) -> Self {
+        Self {
+            config: config.visual_sort.clone(),
+            tracks: SortTracks::new(config),
+        }
+    }
+
+    /// Matches tracks, which have collected enough features, with detections by appearance `features`.
+    ///
+    /// A pair is a candidate if enough of the track features are similar to the detection feature,
+    /// the most similar candidates are matched.
+    /// Returns matched `(track, detection)` index pairs.
+    fn associate_features(&self, features: &[Vec<f32>]) -> Vec<(usize, usize)> {
+        let config = &self.config;
+        let tracks: Vec<_> = (0..self.tracks.tracks.len())
+            .filter(|&t| self.tracks.tracks[t].features.len() >= config.min_track_length)
+            .collect();
+        let cost: Vec<Vec<f64>> = tracks
+            .iter()
+            .map(|&t| {
+                let track_features = &self.tracks.tracks[t].features;
+                features
+                    .iter()
+                    .map(|feature| {
+                        let similarities: Vec<_> = track_features
+                            .iter()
+                            .map(|track_feature| cosine(track_feature, feature))
+                            .filter(|&similarity| similarity >= config.min_similarity)
+                            .collect();
+                        if similarities.len() < config.min_votes.max(1) {
+                            return INVALID_COST;
+                        }
+                        let best = similarities.into_iter().fold(f32::MIN, f32::max);
+                        1.0 - best as f64
+                    })
+                    .collect()
+            })
+            .collect();
+        assign(&cost)
+            .into_iter()
+            .map(|(t, d)| (tracks[t], d))
+            .collect()
+    }
+}
+
+impl Tracker for VisualSortTracker {
+    fn update(&mut self, detections: &Detections, timestamp: Option<Duration>) -> Detections {
+        self.update_with_features(detections, &[], timestamp)
+    }
+
+    fn update_with_features(
+        &mut self,
+        detections: &Detections,
+        features: &[Vec<f32>],
+        timestamp: Option<Duration>,
+    ) -> Detections {
+        self.tracks.advance(timestamp);
+        let features = &features[..features.len().min(detections.len())];
+        let mut matches = self.associate_features(features);
+
+        // Remaining tracks and detections are matched by position.
+        let tracks: Vec<_> = (0..self.tracks.tracks.len())
+            .filter(|t| !matches.iter().any(|(mt, _)| mt == t))
+            .collect();
+        let remaining: Vec<_> = (0..detections.len())
+            .filter(|d| !matches.iter().any(|(_, md)| md == d))
+            .collect();
+        matches.extend(self.tracks.associate(&tracks, &remaining, detections));
+
+        self.tracks
+            .update(detections, &matches, features, self.config.max_observations)
+    }
+
+    fn predict(&mut self, frame_dims: ImgDimensions, timestamp: Option<Duration>) -> Detections {
+        self.tracks.predict(frame_dims, timestamp)
+    }
+}
+
+/// Cosine similarity of two feature vectors, 0 if either of them is zero.
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms > 0.0 {
+        dot / norms
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn visual_sort_reidentifies_by_features() {
+    use crate::{test_detections, test_track};
+
+    let mut tracker = VisualSortTracker::new(&TrackerConfig::default());
+
+    let (a, b) = (vec![1.0, 0.0], vec![0.0, 1.0]);
+    for x in [100.0, 102.0, 104.0, 106.0] {
+        let detections = test_detections(&[(x, 0), (x + 300.0, 0)]);
+        let features = [a.clone(), b.clone()];
+        assert_eq!(
+            test_track(&mut tracker, &detections, &features),
+            [(0, 1), (0, 2)]
+        );
+    }
+    // Both objects are occluded for a couple of frames and reappear elsewhere,
+    // where position alone would not match them.
+    test_track(&mut tracker, &test_detections(&[]), &[]);
+    test_track(&mut tracker, &test_detections(&[]), &[]);
+    let detections = test_detections(&[(250.0, 0), (500.0, 0)]);
+    let features = [b.clone(), a.clone()];
+    assert_eq!(
+        test_track(&mut tracker, &detections, &features),
+        [(0, 2), (0, 1)]
+    );
+}